@@ -4,18 +4,21 @@ use ethers::prelude::Address;
 use ethers::prelude::Bytes;
 use ethers::prelude::U256;
 use futures::channel::mpsc::channel;
+use futures::future;
 use futures::SinkExt;
 use libp2p::Multiaddr;
 use log::{debug, info, warn};
+use std::collections::BTreeMap;
 use std::env;
 use std::error::Error;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
-use ti_node::chains;
 use ti_node::chains::eth;
 use ti_node::chains::eth::PeerPriceFeed;
 use ti_node::fetcher::aggregator;
+use ti_node::fetcher::http;
+use ti_node::fetcher::stream::StreamHub;
 use ti_node::flags;
 use ti_node::processor::gossip;
 use ti_node::processor::gossip::LocalCommand;
@@ -29,6 +32,7 @@ use tokio::time;
 use tokio::time::timeout;
 
 const COLLECT_RESPONSE_TIMEOUT: u64 = 5000;
+const COLLECT_POLL_INTERVAL: u64 = 100;
 const COMMIT_TX_TIMEOUT: u64 = 30000;
 
 #[tokio::main]
@@ -57,7 +61,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         cfg.peers.push(peer_node.to_string());
     }
     Builder::from_env(Env::default().default_filter_or(cfg.log_level.clone())).init();
-    let (topic, mut swarm) = swarm::make_swarm(&cfg).await?;
+    let (topics, mut swarm) = swarm::make_swarm(&cfg).await?;
     // Listen on all interfaces and whatever port the OS assigns
     swarm
         .listen_on(cfg.listen_address.clone().parse().unwrap())
@@ -74,77 +78,127 @@ async fn main() -> Result<(), Box<dyn Error>> {
             Err(e) => warn!("Dial {:?} failed: {:?}", address, e),
         };
     }
-    let (mut sender, receiver) = channel::<LocalCommand>(128);
+    let (sender, receiver) = channel::<LocalCommand>(128);
     let mut private_key = cfg.private_key.clone();
     if private_key.starts_with("$") {
         let var_name = &private_key[1..private_key.len()];
         private_key = env::var(var_name).expect("$NODE_PIVATE_KEY not set");
     }
-    let oracle_stub = chains::eth::new(
-        private_key.clone(),
-        cfg.eth_rpc_url.clone(),
-        cfg.contract_address.clone(),
-    )
-    .await?;
-    let v_bucket = gossip::ValidationBucket::default();
+    let eth_client = eth::new_client(private_key.clone(), cfg.eth_rpc_url.clone()).await?;
+    let mut v_buckets = BTreeMap::<String, gossip::ValidationBucket>::new();
+    let mut oracles = BTreeMap::<String, eth::OracleStub>::new();
+    for feed in &cfg.feeds {
+        v_buckets.insert(feed.coin_name.clone(), gossip::ValidationBucket::default());
+        oracles.insert(
+            feed.coin_name.clone(),
+            eth::new(Arc::clone(&eth_client), feed.contract_address.clone())?,
+        );
+    }
     let s_state = SharedState::default();
     let mut p2p_processor = gossip::new(
         swarm,
-        topic,
+        topics,
         receiver,
-        Arc::clone(&v_bucket),
+        v_buckets.clone(),
+        oracles.clone(),
         Arc::clone(&s_state),
     );
+    cfg.private_key = private_key.clone();
     tokio::task::spawn({
-        let mut cfg_copy = cfg.clone();
-        cfg_copy.private_key = private_key.clone();
+        let cfg_copy = cfg.clone();
         async move {
             p2p_processor.process_p2p_message(cfg_copy).await;
         }
     });
-    let agg = aggregator::new(cfg.mappings.clone(), Arc::clone(&s_state));
-    let mut interval = time::interval(Duration::from_millis(cfg.feed_interval * 1000));
     let web_addr = cfg.web_address.clone();
     let copy_s_state = Arc::clone(&s_state);
     tokio::task::spawn(async move {
         web::start(web_addr, copy_s_state).await;
     });
-    let eth_rpc_url = cfg.eth_rpc_url.clone();
-    let contract_addr = cfg.contract_address.clone();
-    let copy_s_state = Arc::clone(&s_state);
-    let coin_name = cfg.coin_name.clone();
-    tokio::task::spawn(async move {
-        if let Err(error) =
-            eth::start_events_watch(eth_rpc_url, contract_addr, copy_s_state, coin_name).await
-        {
-            warn!("event watcher error: {:?}", error);
-            panic!("halt");
-        }
-    });
+    // one http client for all feeds, so per-host limits hold across them
+    let http_client = http::new(&cfg.http)?;
+    // one stream of each exchange for all feeds, started after all feeds claimed their symbols
+    let streams = StreamHub::new(cfg.stream_max_age * 1000);
+    let mut feed_tasks = Vec::new();
+    for feed in &cfg.feeds {
+        let oracle_stub = oracles[&feed.coin_name].clone();
+        let eth_rpc_url = cfg.eth_rpc_url.clone();
+        let contract_addr = feed.contract_address.clone();
+        let copy_s_state = Arc::clone(&s_state);
+        let coin_name = feed.coin_name.clone();
+        tokio::task::spawn(async move {
+            if let Err(error) =
+                eth::start_events_watch(eth_rpc_url, contract_addr, copy_s_state, coin_name).await
+            {
+                warn!("event watcher error: {:?}", error);
+                panic!("halt");
+            }
+        });
+        let nodes = eth::load_nodes(&oracle_stub, &s_state, &feed.coin_name).await;
+        info!("{} nodes of {}", nodes, feed.coin_name);
+        let agg = aggregator::new(
+            &cfg,
+            feed,
+            http_client.clone(),
+            &streams,
+            Arc::clone(&s_state),
+        );
+        agg.validate()?;
+        agg.claim_streams();
+        agg.validate_sources().await?;
+        feed_tasks.push(tokio::task::spawn(feed_loop(
+            oracle_stub,
+            cfg.clone(),
+            feed.clone(),
+            agg,
+            sender.clone(),
+            Arc::clone(&v_buckets[&feed.coin_name]),
+            Arc::clone(&s_state),
+        )));
+    }
+    aggregator::start_streams(&streams, &http_client);
+    future::join_all(feed_tasks).await;
+    Ok(())
+}
+
+// aggregate price and feed it to the contract periodically, for one coin
+async fn feed_loop(
+    oracle_stub: eth::OracleStub,
+    cfg: flags::Config,
+    feed: flags::FeedConfig,
+    agg: aggregator::Aggregator,
+    mut sender: futures::channel::mpsc::Sender<LocalCommand>,
+    v_bucket: gossip::ValidationBucket,
+    s_state: SharedState,
+) {
+    let mut interval = time::interval(Duration::from_millis(feed.feed_interval * 1000));
     loop {
         let price_result = agg.get_price().await;
-        let weighted_price: u128;
+        let price: aggregator::PriceResult;
         match price_result {
-            Ok(_weighted_price) => {
-                weighted_price = _weighted_price;
+            Ok(result) => {
+                price = result;
             }
             Err(err) => {
                 tokio::time::sleep(Duration::from_millis(eth::CONTRACT_TIMEOUT)).await;
-                warn!("get price from exchange fail; {}", err);
+                warn!(
+                    "get price of {} from exchange fail; {}",
+                    feed.coin_name, err
+                );
                 continue;
             }
         }
         core_loop(
             &oracle_stub,
             &cfg,
+            &feed,
             &mut sender,
-            weighted_price,
-            private_key.clone(),
+            &price,
             &v_bucket,
             &s_state,
         )
         .await;
-        info!("wait a moment to start next feeding");
+        info!("wait a moment to start next feeding of {}", feed.coin_name);
         interval.tick().await;
     }
 }
@@ -153,12 +207,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
 async fn core_loop(
     oracle_stub: &eth::OracleStub,
     cfg: &flags::Config,
+    feed: &flags::FeedConfig,
     sender: &mut futures::channel::mpsc::Sender<LocalCommand>,
-    weighted_price: u128,
-    private_key: String,
+    price: &aggregator::PriceResult,
     v_bucket: &gossip::ValidationBucket,
     s_state: &SharedState,
 ) {
+    let weighted_price = price.price;
     let check_turn = timeout(
         Duration::from_millis(eth::CONTRACT_TIMEOUT),
         oracle_stub.is_my_turn().call(),
@@ -172,10 +227,10 @@ async fn core_loop(
                     let col_sig_future = collect_signatures(
                         oracle_stub,
                         cfg,
+                        feed,
                         sender,
-                        weighted_price,
+                        price,
                         Arc::clone(v_bucket),
-                        private_key.clone(),
                         s_state,
                     );
                     timeout(Duration::from_millis(COMMIT_TX_TIMEOUT), col_sig_future)
@@ -185,6 +240,7 @@ async fn core_loop(
                         });
                 }
                 let refresh_req = RefreshPrice {
+                    coin: feed.coin_name.clone(),
                     price: weighted_price.to_string(),
                     timestamp: utils::timestamp(),
                 };
@@ -207,21 +263,24 @@ async fn core_loop(
 async fn collect_signatures(
     oracle_stub: &eth::OracleStub,
     cfg: &flags::Config,
+    feed: &flags::FeedConfig,
     sender: &mut futures::channel::mpsc::Sender<LocalCommand>,
-    weighted_price: u128,
+    price: &aggregator::PriceResult,
     bucket: gossip::ValidationBucket,
-    private_key: String,
     s_state: &SharedState,
 ) {
+    let weighted_price = price.price;
     let feed_count = match eth::get_feed_count(oracle_stub).await {
         Some(value) => value,
         None => return,
     };
     let valid_request = gossip::ValidateRequest {
-        coin: cfg.coin_name.clone(),
+        coin: feed.coin_name.clone(),
         feed_count: feed_count.as_u64(),
         timestamp: utils::timestamp(),
-        price: weighted_price.to_string(),
+        price: weighted_price,
+        dispersion: price.dispersion(),
+        sources: price.sources,
     };
     {
         let mut v_bucket = bucket.lock().unwrap();
@@ -234,13 +293,29 @@ async fn collect_signatures(
         for gc_key in gc_keys {
             v_bucket.remove(&gc_key);
         }
-        //call p2p network to delivery validation request to other peers
-        sender
-            .send(LocalCommand::VReq(valid_request))
-            .await
-            .unwrap();
     }
-    tokio::time::sleep(Duration::from_millis(COLLECT_RESPONSE_TIMEOUT)).await; //wait for result at most 5 seconds
+    //call p2p network to delivery validation request to other peers
+    sender
+        .send(LocalCommand::VReq(valid_request))
+        .await
+        .unwrap();
+    //wait for result at most 5 seconds, or until signatures are enough
+    let nodes = eth::nodes_count(s_state, &feed.coin_name);
+    let deadline = time::Instant::now() + Duration::from_millis(COLLECT_RESPONSE_TIMEOUT);
+    while time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(COLLECT_POLL_INTERVAL)).await;
+        let signed = match bucket.lock().unwrap().get(&feed_count.as_u64()) {
+            Some(responses) => responses
+                .iter()
+                .filter(|resp| verify_response(resp))
+                .count(),
+            None => 0,
+        };
+        if nodes > 0 && signed + 1 >= eth::quorum(nodes) {
+            info!("{} of {} nodes signed, quorum reached", signed + 1, nodes);
+            break;
+        }
+    }
     let validated_response_list: Vec<ValidateResponse>;
     {
         let v_bucket = bucket.lock().unwrap();
@@ -260,6 +335,8 @@ async fn collect_signatures(
             let copy_of_report = validated_response_list.clone();
             s_state
                 .peers_report
+                .entry(feed.coin_name.clone())
+                .or_default()
                 .insert(feed_count.as_u64(), copy_of_report);
         }
         info!(
@@ -267,21 +344,14 @@ async fn collect_signatures(
             validated_response_list
         );
         for price_signed in validated_response_list {
-            let verify_result = eth::verify_sig(
-                price_signed.sig.clone(),
-                price_signed.coin.clone(),
-                price_signed.price.parse::<u128>().unwrap_or_else(|_| 0),
-                price_signed.timestamp,
-                price_signed.address.clone(),
-            );
-            if !verify_result {
+            if !verify_response(&price_signed) {
                 warn!("verify signature failed from {:}", price_signed.address);
                 continue;
             }
             let price_feed = PeerPriceFeed {
                 peer_address: Address::from_str(price_signed.address.as_str()).unwrap(),
                 sig: Bytes::from_str(&price_signed.sig.as_str()).unwrap(),
-                price: U256::from(price_signed.price.parse::<u128>().unwrap()),
+                price: U256::from(price_signed.price),
                 timestamp: U256::from(price_signed.timestamp),
             };
             let allowed = oracle_stub
@@ -305,8 +375,8 @@ async fn collect_signatures(
     //prepare self sign
     let ts_seconds = utils::timestamp() / 1000;
     let (mysig, myaddr) = eth::sign_price_info(
-        private_key.clone(),
-        cfg.coin_name.clone(),
+        cfg.private_key.clone(),
+        feed.coin_name.clone(),
         weighted_price,
         ts_seconds,
     );
@@ -324,7 +394,7 @@ async fn collect_signatures(
         info!("{} -> price: {}", price_info.peer_address, price_info.price);
     }
     match oracle_stub
-        .feed_price(cfg.coin_name.clone(), peers_price)
+        .feed_price(feed.coin_name.clone(), peers_price)
         .gas_price(eth::from_gwei(cfg.fee_per_gas))
         .send()
         .await
@@ -345,3 +415,14 @@ async fn collect_signatures(
         }
     }
 }
+
+// verify the price signature of a response
+fn verify_response(price_signed: &ValidateResponse) -> bool {
+    eth::verify_sig(
+        price_signed.sig.clone(),
+        price_signed.coin.clone(),
+        price_signed.price,
+        price_signed.timestamp,
+        price_signed.address.clone(),
+    )
+}
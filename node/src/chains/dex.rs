@@ -0,0 +1,340 @@
+use ethers::contract::{EthLogDecode, Event};
+use ethers::prelude::*;
+use log::warn;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::{convert::TryFrom, sync::Arc};
+use strum_macros::Display;
+
+abigen!(
+    ERC20,
+    r#"[
+        function decimals() external view returns (uint8)
+        function symbol() external view returns (string)
+        function balanceOf(address account) external view returns (uint256)
+    ]"#,
+);
+
+// pools of different protocols are put in separated modules, as their events may have the same name
+pub mod curve {
+    use ethers::prelude::*;
+    abigen!(
+        CurvePool,
+        r#"[
+            function coins(uint256 i) external view returns (address)
+            function get_dy(int128 i, int128 j, uint256 dx) external view returns (uint256)
+            event TokenExchange(address indexed buyer, int128 sold_id, uint256 tokens_sold, int128 bought_id, uint256 tokens_bought)
+        ]"#,
+    );
+}
+
+pub mod uniswapv2 {
+    use ethers::prelude::*;
+    abigen!(
+        UniswapV2Pair,
+        r#"[
+            function token0() external view returns (address)
+            function token1() external view returns (address)
+            function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast)
+            event Swap(address indexed sender, uint256 amount0In, uint256 amount1In, uint256 amount0Out, uint256 amount1Out, address indexed to)
+        ]"#,
+    );
+}
+
+pub mod uniswapv3 {
+    use ethers::prelude::*;
+    abigen!(
+        UniswapV3Pool,
+        r#"[
+            function token0() external view returns (address)
+            function token1() external view returns (address)
+            function slot0() external view returns (uint160 sqrtPriceX96, int24 tick, uint16 observationIndex, uint16 observationCardinality, uint16 observationCardinalityNext, uint8 feeProtocol, bool unlocked)
+            event Swap(address indexed sender, address indexed recipient, int256 amount0, int256 amount1, uint160 sqrtPriceX96, uint128 liquidity, int24 tick)
+        ]"#,
+    );
+}
+
+// about 12 seconds per block on Ethereum mainnet
+pub const BLOCKS_PER_DAY: u64 = 7200;
+// blocks covered by one eth_getLogs, as RPC providers limit the range or the results of a query
+pub const LOG_PAGE_BLOCKS: u64 = 2000;
+
+// placeholder address used by pools to represent the native ether
+pub const NATIVE_ETH: &str = "0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE";
+
+#[derive(Debug, Display)]
+pub enum DexError {
+    NoBlock(String),
+}
+impl std::error::Error for DexError {}
+
+// ChainReader reads state of DEX pools through eth_call, at the latest block or a pinned one
+#[derive(Clone)]
+pub struct ChainReader {
+    pub rpc_url: String,
+    pub block: Option<u64>,
+    pub volumes: VolumeCache, //shared by clones, so that each pool keeps its last volume read in full
+}
+
+// state of a pool observed at one block, or indexed by a subgraph
+#[derive(Debug, Clone)]
+pub struct PoolState {
+    pub tokens: [String; 2], // symbols of token0 and token1
+    pub price: f64,          // price of token0 quoted by token1
+    pub volume: f64,         // amount of token0 traded in the last 24 hours
+    pub depths: [f64; 2],    // amounts of token0 and token1 held by the pool
+}
+
+impl ChainReader {
+    pub fn provider(&self) -> Result<Arc<Provider<Http>>, Box<dyn Error + Send + Sync>> {
+        Ok(Arc::new(Provider::<Http>::try_from(self.rpc_url.as_str())?))
+    }
+
+    // get the number and the timestamp(in ms) of the block to read
+    pub async fn block_info(
+        &self,
+        provider: &Provider<Http>,
+    ) -> Result<(u64, u64), Box<dyn Error + Send + Sync>> {
+        let block_id: BlockId = match self.block {
+            Some(number) => number.into(),
+            None => BlockNumber::Latest.into(),
+        };
+        let block = provider
+            .get_block(block_id)
+            .await?
+            .ok_or(DexError::NoBlock(format!("{:?}", block_id)))?;
+        let number = block
+            .number
+            .ok_or(DexError::NoBlock(format!("{:?}", block_id)))?;
+        Ok((number.as_u64(), block.timestamp.as_u64() * 1000))
+    }
+}
+
+// get decimals of a token, native ether is supported too
+pub async fn token_decimals(
+    provider: Arc<Provider<Http>>,
+    token: Address,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    if token == Address::from_str(NATIVE_ETH)? {
+        return Ok(18);
+    }
+    Ok(ERC20::new(token, provider).decimals().call().await?)
+}
+
+// get symbol of a token, native ether is supported too
+pub async fn token_symbol(
+    provider: Arc<Provider<Http>>,
+    token: Address,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    if token == Address::from_str(NATIVE_ETH)? {
+        return Ok("ETH".into());
+    }
+    Ok(ERC20::new(token, provider).symbol().call().await?)
+}
+
+// get symbols of token0 and token1 of a uniswap v2 like pair or a uniswap v3 pool, as both have
+// the same getters
+pub async fn pool_tokens(
+    provider: Arc<Provider<Http>>,
+    address: Address,
+) -> Result<[String; 2], Box<dyn Error + Send + Sync>> {
+    let pair = uniswapv2::UniswapV2Pair::new(address, provider.clone());
+    let token0 = pair.token_0().call().await?;
+    let token1 = pair.token_1().call().await?;
+    Ok([
+        token_symbol(provider.clone(), token0).await?,
+        token_symbol(provider, token1).await?,
+    ])
+}
+
+// convert raw token amount into float number
+pub fn to_float(amount: U256, decimals: u8) -> Result<f64, Box<dyn Error + Send + Sync>> {
+    Ok(ethers::utils::format_units(amount, decimals as u32)?.parse::<f64>()?)
+}
+
+// VolumeCache keeps the last volume of each pool read from all pages of its logs
+pub type VolumeCache = Arc<Mutex<BTreeMap<String, f64>>>;
+
+// keep_volume remembers the volume of a pool read from all pages of its logs,
+// or falls back to the last one if some page failed, the pool only fails if it has none yet
+pub fn keep_volume(
+    volumes: &VolumeCache,
+    key: &str,
+    volume: Result<f64, Box<dyn Error + Send + Sync>>,
+) -> Result<f64, Box<dyn Error + Send + Sync>> {
+    let mut volumes = volumes.lock().unwrap();
+    match volume {
+        Ok(volume) => {
+            volumes.insert(key.into(), volume);
+            Ok(volume)
+        }
+        Err(err) => match volumes.get(key) {
+            Some(last) => {
+                warn!("volume of {} kept at {} as logs failed: {}", key, last, err);
+                Ok(*last)
+            }
+            None => Err(err),
+        },
+    }
+}
+
+// log_pages splits blocks from `from` to `to`, both included, into ranges of LOG_PAGE_BLOCKS at most
+pub fn log_pages(from: u64, to: u64) -> Vec<(u64, u64)> {
+    let mut pages = Vec::new();
+    let mut start = from;
+    while start <= to {
+        let end = to.min(start.saturating_add(LOG_PAGE_BLOCKS - 1));
+        pages.push((start, end));
+        if end == u64::MAX {
+            break;
+        }
+        start = end + 1;
+    }
+    pages
+}
+
+// query_logs reads events of a contract over blocks from `from` to `to` page by page, and merges them
+pub async fn query_logs<D: EthLogDecode>(
+    mut event: Event<'_, Provider<Http>, D>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<D>, Box<dyn Error + Send + Sync>> {
+    let mut logs = Vec::new();
+    for (start, end) in log_pages(from, to) {
+        event.filter = event.filter.from_block(start).to_block(end);
+        logs.extend(event.query().await?);
+    }
+    Ok(logs)
+}
+
+// read price from reserves of a uniswap v2 like pair, and volume from its swap logs
+pub async fn read_v2_pair(
+    chain: &ChainReader,
+    provider: Arc<Provider<Http>>,
+    address: Address,
+    block: u64,
+) -> Result<PoolState, Box<dyn Error + Send + Sync>> {
+    let pair = uniswapv2::UniswapV2Pair::new(address, provider.clone());
+    let token0 = pair.token_0().block(block).call().await?;
+    let token1 = pair.token_1().block(block).call().await?;
+    let decimals0 = token_decimals(provider.clone(), token0).await?;
+    let decimals1 = token_decimals(provider.clone(), token1).await?;
+    let (reserve0, reserve1, _) = pair.get_reserves().block(block).call().await?;
+    let price =
+        to_float(U256::from(reserve1), decimals1)? / to_float(U256::from(reserve0), decimals0)?;
+    let swaps = query_logs(
+        pair.event::<uniswapv2::SwapFilter>(),
+        block.saturating_sub(BLOCKS_PER_DAY),
+        block,
+    )
+    .await
+    .and_then(|swaps| {
+        let mut volume = U256::zero();
+        for swap in swaps {
+            volume += swap.amount_0_in + swap.amount_0_out;
+        }
+        to_float(volume, decimals0)
+    });
+    let volume = keep_volume(&chain.volumes, &format!("{:?}", address), swaps)?;
+    Ok(PoolState {
+        tokens: [
+            token_symbol(provider.clone(), token0).await?,
+            token_symbol(provider, token1).await?,
+        ],
+        price: price,
+        volume: volume,
+        depths: [
+            to_float(U256::from(reserve0), decimals0)?,
+            to_float(U256::from(reserve1), decimals1)?,
+        ],
+    })
+}
+
+// read price from slot0 of a uniswap v3 pool, and volume from its swap logs
+pub async fn read_v3_pool(
+    chain: &ChainReader,
+    provider: Arc<Provider<Http>>,
+    address: Address,
+    block: u64,
+) -> Result<PoolState, Box<dyn Error + Send + Sync>> {
+    let pool = uniswapv3::UniswapV3Pool::new(address, provider.clone());
+    let token0 = pool.token_0().block(block).call().await?;
+    let token1 = pool.token_1().block(block).call().await?;
+    let decimals0 = token_decimals(provider.clone(), token0).await?;
+    let decimals1 = token_decimals(provider.clone(), token1).await?;
+    let slot0 = pool.slot_0().block(block).call().await?;
+    // price = (sqrtPriceX96 / 2^96)^2, adjusted by decimals of tokens
+    let sqrt_price = to_float(slot0.0, 0)? / 2f64.powi(96);
+    let price = sqrt_price * sqrt_price * 10f64.powi(decimals0 as i32 - decimals1 as i32);
+    let swaps = query_logs(
+        pool.event::<uniswapv3::SwapFilter>(),
+        block.saturating_sub(BLOCKS_PER_DAY),
+        block,
+    )
+    .await
+    .and_then(|swaps| {
+        let mut volume = U256::zero();
+        for swap in swaps {
+            volume += swap.amount_0.abs().into_raw();
+        }
+        to_float(volume, decimals0)
+    });
+    let volume = keep_volume(&chain.volumes, &format!("{:?}", address), swaps)?;
+    let balance0 = ERC20::new(token0, provider.clone())
+        .balance_of(address)
+        .block(block)
+        .call()
+        .await?;
+    let balance1 = ERC20::new(token1, provider.clone())
+        .balance_of(address)
+        .block(block)
+        .call()
+        .await?;
+    Ok(PoolState {
+        tokens: [
+            token_symbol(provider.clone(), token0).await?,
+            token_symbol(provider, token1).await?,
+        ],
+        price: price,
+        volume: volume,
+        depths: [
+            to_float(balance0, decimals0)?,
+            to_float(balance1, decimals1)?,
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_log_pages() {
+        assert_eq!(
+            log_pages(12800, 20000),
+            vec![
+                (12800, 14799),
+                (14800, 16799),
+                (16800, 18799),
+                (18800, 20000)
+            ]
+        );
+        assert_eq!(log_pages(0, 0), vec![(0, 0)]);
+        assert_eq!(log_pages(10, 9), vec![]);
+        assert_eq!(
+            log_pages(u64::MAX - 1, u64::MAX),
+            vec![(u64::MAX - 1, u64::MAX)]
+        );
+    }
+
+    #[test]
+    fn test_keep_volume() {
+        let volumes = VolumeCache::default();
+        let failed = || Err("page failed".into());
+        assert!(keep_volume(&volumes, "0xabc", failed()).is_err());
+        assert_eq!(keep_volume(&volumes, "0xabc", Ok(12.5)).unwrap(), 12.5);
+        assert_eq!(keep_volume(&volumes, "0xabc", failed()).unwrap(), 12.5);
+        assert!(keep_volume(&volumes, "0xdef", failed()).is_err());
+    }
+}
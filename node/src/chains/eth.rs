@@ -5,6 +5,7 @@ use std::collections::BTreeMap;
 use std::error::Error;
 use std::str::FromStr;
 use std::{convert::TryFrom, sync::Arc};
+use strum_macros::Display;
 use tokio::time;
 use tokio::time::timeout;
 use tokio::time::Duration;
@@ -13,7 +14,9 @@ use crate::processor::web::{ChainEvent, PeerReport, SharedState};
 
 abigen!(TIOracle, "../contracts/out/TIOracle.sol/TIOracle.json");
 
-pub type OracleStub = TIOracle<SignerMiddleware<Provider<Http>, Wallet<SigningKey>>>;
+// signer shared by all feeds, so that transactions of different contracts use one nonce stream
+pub type EthClient = NonceManagerMiddleware<SignerMiddleware<Provider<Http>, Wallet<SigningKey>>>;
+pub type OracleStub = TIOracle<EthClient>;
 
 pub const CONTRACT_TIMEOUT: u64 = 5000;
 pub const MAX_HISTORY_EVENTS: usize = 1440;
@@ -66,36 +69,131 @@ pub fn verify_sig(sig: String, coin: String, price: u128, timestamp: u64, addres
         .is_ok()
 }
 
-pub async fn new(
+// sign keccak256 of a message, e.g. the encoding of a gossip message, then return signature and address
+pub fn sign_message(private_key: &str, msg: &[u8]) -> (String, String) {
+    let pk = private_key.parse::<LocalWallet>().unwrap();
+    let msg_hash = ethers::utils::keccak256(msg);
+    return (
+        pk.sign_hash(H256::from(msg_hash)).to_string(),
+        format!("{:?}", pk.address()),
+    );
+}
+
+// verify signature of a message signed by sign_message
+pub fn verify_message(sig: &str, msg: &[u8], address: &str) -> bool {
+    let (sig_obj, address_obj) = match (Signature::from_str(sig), Address::from_str(address)) {
+        (Ok(sig_obj), Ok(address_obj)) => (sig_obj, address_obj),
+        (sig_obj, address_obj) => {
+            warn!(
+                "message signature error: {:?}, {:?}",
+                sig_obj.err(),
+                address_obj.err()
+            );
+            return false;
+        }
+    };
+    sig_obj
+        .verify(H256::from(ethers::utils::keccak256(msg)), address_obj)
+        .is_ok()
+}
+
+// query_node asks the contract whether an address is a whitelisted node
+pub async fn query_node(oracle_stub: &OracleStub, address: &str) -> Option<bool> {
+    let address = Address::from_str(address).ok()?;
+    match timeout(
+        Duration::from_millis(CONTRACT_TIMEOUT),
+        oracle_stub.query_node(address).call(),
+    )
+    .await
+    {
+        Ok(Ok(allowed)) => Some(allowed),
+        Ok(Err(err)) => {
+            warn!("query node err: {:?}", err);
+            None
+        }
+        Err(timeout_err) => {
+            warn!("query node timeout: {}", timeout_err);
+            None
+        }
+    }
+}
+
+// set_node caches whether an address is a whitelisted node of the contract of a coin
+pub fn set_node(s_state: &SharedState, coin_name: &str, address: Address, allowed: bool) {
+    s_state
+        .lock()
+        .unwrap()
+        .nodes
+        .entry(coin_name.into())
+        .or_default()
+        .insert(format!("{:?}", address), allowed);
+}
+
+// MAX_NODES bounds the scan of nodes of a contract
+const MAX_NODES: u64 = 256;
+
+// load_nodes caches all nodes of the contract of a coin, by reading the list of nodes until out of range
+pub async fn load_nodes(oracle_stub: &OracleStub, s_state: &SharedState, coin_name: &str) -> usize {
+    let mut count = 0;
+    for i in 0..MAX_NODES {
+        let node = timeout(
+            Duration::from_millis(CONTRACT_TIMEOUT),
+            oracle_stub.nodes(U256::from(i)).call(),
+        )
+        .await;
+        match node {
+            Ok(Ok(address)) => {
+                set_node(s_state, coin_name, address, true);
+                count += 1;
+            }
+            _ => break,
+        }
+    }
+    count
+}
+
+// nodes_count returns how many whitelisted nodes of a coin are cached
+pub fn nodes_count(s_state: &SharedState, coin_name: &str) -> usize {
+    s_state
+        .lock()
+        .unwrap()
+        .nodes
+        .get(coin_name)
+        .map_or(0, |nodes| {
+            nodes.values().filter(|allowed| **allowed).count()
+        })
+}
+
+// quorum is how many signatures, the leader's included, the contract accepts, more than 2/3 of nodes
+pub fn quorum(nodes: usize) -> usize {
+    nodes * 2 / 3 + 1
+}
+
+pub async fn new_client(
     private_key: String,
     rpc_url: String,
-    contract_address: String,
-) -> Result<OracleStub, Box<dyn Error>> {
-    let provider = Arc::new({
-        // connect to the network
-        let provider = Provider::<Http>::try_from(rpc_url.clone())?;
-        print!("eth rpc url: {}", rpc_url);
-        let chain_id = provider.get_chainid().await?;
-        // this wallet's private key
-        let wallet = private_key
-            .parse::<LocalWallet>()?
-            .with_chain_id(chain_id.as_u64());
-        SignerMiddleware::new(provider, wallet)
-    });
+) -> Result<Arc<EthClient>, Box<dyn Error>> {
+    // connect to the network
+    let provider = Provider::<Http>::try_from(rpc_url.clone())?;
+    print!("eth rpc url: {}", rpc_url);
+    let chain_id = provider.get_chainid().await?;
+    // this wallet's private key
+    let wallet = private_key
+        .parse::<LocalWallet>()?
+        .with_chain_id(chain_id.as_u64());
+    let address = wallet.address();
+    let signer = SignerMiddleware::new(provider, wallet);
+    Ok(Arc::new(NonceManagerMiddleware::new(signer, address)))
+}
+
+pub fn new(client: Arc<EthClient>, contract_address: String) -> Result<OracleStub, Box<dyn Error>> {
     let hex_addr = contract_address.parse::<Address>()?;
-    let oracle_stub = TIOracle::new(hex_addr, provider.clone());
+    let oracle_stub = TIOracle::new(hex_addr, client);
     Ok(oracle_stub)
 }
 
 // get_feed_count get the how many times of feeding already committed
-pub async fn get_feed_count(
-    oracle_stub: &TIOracle<
-        ethers::prelude::SignerMiddleware<
-            ethers::prelude::Provider<ethers::prelude::Http>,
-            ethers::prelude::Wallet<ethers::prelude::k256::ecdsa::SigningKey>,
-        >,
-    >,
-) -> Option<U256> {
+pub async fn get_feed_count(oracle_stub: &OracleStub) -> Option<U256> {
     let feed_count: U256;
     let feed_count_result = timeout(
         Duration::from_millis(CONTRACT_TIMEOUT),
@@ -120,6 +218,90 @@ pub async fn get_feed_count(
     Some(feed_count)
 }
 
+#[derive(Debug, Display, PartialEq)]
+pub enum LeaderError {
+    StaleFeedCount(String),
+    FutureFeedCount(String),
+    NotLeader(String),
+    Contract(String),
+}
+impl std::error::Error for LeaderError {}
+
+// check_feed_count compares the feed count requested with the one on chain, only the current one can be signed
+pub fn check_feed_count(requested: u64, on_chain: u64) -> Result<(), LeaderError> {
+    let detail = format!("requested {}, on chain {}", requested, on_chain);
+    if requested < on_chain {
+        return Err(LeaderError::StaleFeedCount(detail));
+    }
+    if requested > on_chain {
+        return Err(LeaderError::FutureFeedCount(detail));
+    }
+    Ok(())
+}
+
+// LeaderCache keeps the round and its leader decided by the contract, by feed count
+pub type LeaderCache = BTreeMap<u64, (U256, Address)>;
+
+// check_leader tells whether the requester is the node expected to feed for feed_count,
+// isMyTurn is called on behalf of the requester, so both round-robin and timeout modes are followed
+pub async fn check_leader(
+    oracle_stub: &OracleStub,
+    leaders: &mut LeaderCache,
+    requester: &str,
+    feed_count: u64,
+) -> Result<(), LeaderError> {
+    let on_chain = get_feed_count(oracle_stub)
+        .await
+        .ok_or_else(|| LeaderError::Contract("get feed count fail".into()))?;
+    leaders.retain(|count, _| *count >= on_chain.as_u64());
+    check_feed_count(feed_count, on_chain.as_u64())?;
+    let requester_addr =
+        Address::from_str(requester).map_err(|err| LeaderError::NotLeader(err.to_string()))?;
+    let is_turn = timeout(
+        Duration::from_millis(CONTRACT_TIMEOUT),
+        oracle_stub.is_my_turn().from(requester_addr).call(),
+    )
+    .await
+    .map_err(|err| LeaderError::Contract(err.to_string()))?
+    .map_err(|err| LeaderError::Contract(err.to_string()))?;
+    if is_turn {
+        return Ok(());
+    }
+    // the leader expected is only for the log, it is decided once for each feed count
+    let decided = match leaders.get(&feed_count) {
+        Some(decided) => Ok(*decided),
+        None => decide_leader(oracle_stub).await,
+    };
+    let expected = match decided {
+        Ok((round, leader)) => {
+            leaders.insert(feed_count, (round, leader));
+            format!("{:?} of round {}", leader, round)
+        }
+        Err(err) => err,
+    };
+    Err(LeaderError::NotLeader(format!(
+        "{} is not the leader, expected {}",
+        requester, expected
+    )))
+}
+
+// decide_leader asks the contract for the last round and the node to feed in it
+async fn decide_leader(oracle_stub: &OracleStub) -> Result<(U256, Address), String> {
+    let contract_timeout = Duration::from_millis(CONTRACT_TIMEOUT);
+    let round = timeout(contract_timeout, oracle_stub.last_round().call())
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| err.to_string())?;
+    let leader = timeout(
+        contract_timeout,
+        oracle_stub.decide_valid_node(round).call(),
+    )
+    .await
+    .map_err(|err| err.to_string())?
+    .map_err(|err| err.to_string())?;
+    Ok((round, leader))
+}
+
 pub fn from_gwei(gwei: f64) -> U256 {
     u256_from_f64_saturating(gwei * 1.0e9_f64)
 }
@@ -187,12 +369,15 @@ pub async fn start_events_watch(
                     match event {
                         TIOracleEvents::NodeAddedFilter(add_event) => {
                             debug!("{:?}", add_event);
+                            set_node(&s_state, &coin_name, add_event.new_node, true);
                         }
                         TIOracleEvents::NodeKickedFilter(kick_event) => {
                             debug!("{:?}", kick_event);
+                            set_node(&s_state, &coin_name, kick_event.removed_node, false);
                         }
                         TIOracleEvents::NodeRemovedFilter(remove_event) => {
                             debug!("{:?}", remove_event);
+                            set_node(&s_state, &coin_name, remove_event.removed_node, false);
                         }
                         TIOracleEvents::PriceFeedFilter(feed_event) => {
                             let feed_count = feed_event.feed_count.as_u64();
@@ -274,6 +459,47 @@ mod tests {
         println!("{}, {}", node3_pk.address(), sig3);
     }
 
+    #[test]
+    fn test_sign_message() {
+        let pk = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+        let msg = br#"{"type":"VReq","coin":"btc"}"#;
+        let (sig, address) = sign_message(pk, msg);
+        assert_eq!(address, "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266");
+        assert!(verify_message(&sig, msg, &address));
+        assert!(!verify_message(
+            &sig,
+            br#"{"type":"VReq","coin":"eth"}"#,
+            &address
+        ));
+        assert!(!verify_message(
+            &sig,
+            msg,
+            "0x70997970c51812dc3a010c7d01b50e0d17dc79c8"
+        ));
+        assert!(!verify_message("0x00", msg, &address));
+    }
+
+    #[test]
+    fn test_quorum() {
+        assert_eq!(quorum(1), 1);
+        assert_eq!(quorum(3), 3);
+        assert_eq!(quorum(4), 3);
+        assert_eq!(quorum(7), 5);
+    }
+
+    #[test]
+    fn test_check_feed_count() {
+        assert_eq!(check_feed_count(7, 7), Ok(()));
+        assert!(matches!(
+            check_feed_count(6, 7),
+            Err(LeaderError::StaleFeedCount(_))
+        ));
+        assert!(matches!(
+            check_feed_count(8, 7),
+            Err(LeaderError::FutureFeedCount(_))
+        ));
+    }
+
     #[test]
     fn generate_btcprice_feed() {
         // sign message from your wallet and print out signature produced.
@@ -1 +1,2 @@
+pub mod dex;
 pub mod eth;
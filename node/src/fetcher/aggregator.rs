@@ -1,110 +1,802 @@
-use crate::processor::web::SharedState;
+use crate::chains::dex::ChainReader;
+use crate::fetcher;
+use crate::flags::{self, Config, DexMode, FeedConfig, PriceSource, ResilienceConfig};
+use crate::processor::utils;
+use crate::processor::web::{PairsSnapshot, RejectedPair, SharedState, SkippedSymbol};
 
-use super::ftx::{self, Ftx};
+use super::curve;
+use super::expression::Symbol;
+use super::http::HttpClient;
 use super::kucoin::{self, Kucoin};
 use super::okex::{self, OkEx};
+use super::pool::{self, PoolError};
+use super::resilience;
+use super::rest;
+use super::stream::{StreamHub, StreamSpec, TickerCache};
 use super::sushiswap::{self, Sushiswap};
-use super::{binance, coinbase, uniswapv2, uniswapv3, Exchange, PairInfo, PRECESIONS_REPRESENT};
+use super::{
+    binance, bitfinex, bybit, coinbase, expression, gateio, huobi, kraken, uniswapv2, uniswapv3,
+    Exchange, PairInfo, Side, VolumeUnit, PRECESIONS_REPRESENT,
+};
 use binance::Binance;
 use coinbase::Coinbase;
 use futures::future;
 use log::{debug, info, warn};
-use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::error::Error;
+use std::sync::Mutex;
 use std::vec::Vec;
 use strum_macros::Display;
 use uniswapv2::UniswapV2;
 use uniswapv3::UniswapV3;
 
+// Context holds what exchanges of a feed are built from
+pub struct Context<'a> {
+    pub cfg: &'a Config,
+    pub legs: &'a BTreeMap<String, Vec<String>>, //exchange -> symbols to fetch
+    pub coin: &'a str,
+    pub http: HttpClient,
+    pub chain: ChainReader,
+    pub streams: &'a StreamHub,
+}
+
+impl Context<'_> {
+    // DEX fetchers read chain state directly in onchain mode, otherwise they query subgraphs
+    fn dex_chain(&self) -> Option<ChainReader> {
+        match self.cfg.dex_mode {
+            DexMode::Onchain => Some(self.chain.clone()),
+            DexMode::Subgraph => None,
+        }
+    }
+
+    // shared ticker cache of the exchange, only if streaming is enabled and the feed uses it
+    fn stream(&self, exchange: &str) -> Option<TickerCache> {
+        if !self.cfg.streaming || !self.legs.contains_key(exchange) {
+            return None;
+        }
+        Some(self.streams.cache(exchange))
+    }
+}
+
+// Registration describes a built-in exchange, and other names it is known by in config
+pub struct Registration {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub build: fn(&Context) -> Box<dyn Exchange>,
+    pub stream: Option<fn(&HttpClient) -> Box<dyn StreamSpec>>, //ticker stream shared by all feeds
+    pub depth: bool, //whether its pairs report depth, which liquidity_weighted feeds rely on
+}
+
+// exchanges implemented in rust, all of them are enabled unless disabled in config
+pub const EXCHANGES: &[Registration] = &[
+    Registration {
+        name: binance::NAME,
+        aliases: &[],
+        stream: Some(|_| Box::new(binance::TickerStream {})),
+        depth: false,
+        build: |ctx| {
+            Box::new(Binance {
+                http: ctx.http.clone(),
+                stream: ctx.stream(binance::NAME),
+                ..Default::default()
+            })
+        },
+    },
+    Registration {
+        name: bitfinex::NAME,
+        aliases: &[],
+        stream: None,
+        depth: false,
+        build: |ctx| {
+            Box::new(bitfinex::Bitfinex {
+                http: ctx.http.clone(),
+                ..Default::default()
+            })
+        },
+    },
+    Registration {
+        name: bybit::NAME,
+        aliases: &[],
+        stream: None,
+        depth: false,
+        build: |ctx| {
+            Box::new(bybit::Bybit {
+                http: ctx.http.clone(),
+                ..Default::default()
+            })
+        },
+    },
+    Registration {
+        name: coinbase::NAME,
+        aliases: &[],
+        stream: Some(|_| Box::new(coinbase::TickerStream {})),
+        depth: false,
+        build: |ctx| {
+            Box::new(Coinbase {
+                http: ctx.http.clone(),
+                stream: ctx.stream(coinbase::NAME),
+                ..Default::default()
+            })
+        },
+    },
+    Registration {
+        name: curve::NAME,
+        aliases: &[],
+        stream: None,
+        depth: false,
+        build: |ctx| Box::new(curve::new(ctx.chain.clone())),
+    },
+    Registration {
+        name: gateio::NAME,
+        aliases: &["gate"],
+        stream: None,
+        depth: false,
+        build: |ctx| {
+            Box::new(gateio::Gateio {
+                http: ctx.http.clone(),
+                ..Default::default()
+            })
+        },
+    },
+    Registration {
+        name: huobi::NAME,
+        aliases: &["htx"],
+        stream: None,
+        depth: false,
+        build: |ctx| {
+            Box::new(huobi::Huobi {
+                http: ctx.http.clone(),
+                ..Default::default()
+            })
+        },
+    },
+    Registration {
+        name: kraken::NAME,
+        aliases: &[],
+        stream: None,
+        depth: false,
+        build: |ctx| {
+            Box::new(kraken::Kraken {
+                http: ctx.http.clone(),
+                ..Default::default()
+            })
+        },
+    },
+    Registration {
+        name: kucoin::NAME,
+        aliases: &[],
+        stream: Some(|http| Box::new(kucoin::TickerStream { http: http.clone() })),
+        depth: false,
+        build: |ctx| {
+            Box::new(Kucoin {
+                http: ctx.http.clone(),
+                stream: ctx.stream(kucoin::NAME),
+                ..Default::default()
+            })
+        },
+    },
+    Registration {
+        name: okex::NAME,
+        aliases: &["okx"],
+        stream: Some(|_| Box::new(okex::TickerStream {})),
+        depth: false,
+        build: |ctx| {
+            Box::new(OkEx {
+                http: ctx.http.clone(),
+                stream: ctx.stream(okex::NAME),
+                ..Default::default()
+            })
+        },
+    },
+    Registration {
+        name: sushiswap::NAME,
+        aliases: &[],
+        stream: None,
+        depth: true,
+        build: |ctx| {
+            Box::new(Sushiswap {
+                http: ctx.http.clone(),
+                chain: ctx.dex_chain(),
+                coin: ctx.coin.into(),
+                ..Default::default()
+            })
+        },
+    },
+    Registration {
+        name: uniswapv2::NAME,
+        aliases: &[],
+        stream: None,
+        depth: true,
+        build: |ctx| {
+            Box::new(UniswapV2 {
+                http: ctx.http.clone(),
+                chain: ctx.dex_chain(),
+                coin: ctx.coin.into(),
+                ..Default::default()
+            })
+        },
+    },
+    Registration {
+        name: uniswapv3::NAME,
+        aliases: &[],
+        stream: None,
+        depth: true,
+        build: |ctx| {
+            Box::new(UniswapV3 {
+                http: ctx.http.clone(),
+                chain: ctx.dex_chain(),
+                coin: ctx.coin.into(),
+                ..Default::default()
+            })
+        },
+    },
+];
+
+// supplies_depth tells whether pairs of the built-in exchange report depth
+pub fn supplies_depth(name: &str) -> bool {
+    let name = canonical_name(name);
+    EXCHANGES
+        .iter()
+        .any(|registration| registration.name == name && registration.depth)
+}
+
+// canonical_name resolves aliases of built-in exchanges, other names are kept as they are
+pub fn canonical_name(name: &str) -> String {
+    EXCHANGES
+        .iter()
+        .find(|registration| registration.aliases.contains(&name))
+        .map(|registration| registration.name)
+        .unwrap_or(name)
+        .into()
+}
+
 pub struct Aggregator {
+    coin_name: String,
+    asset: Option<String>, //base volumes counted in other assets are refused, if configured
+    min_volume: f64,       //in base units, while volumes of pairs are normalized to quote notional
     data_sources: BTreeMap<String, Box<dyn Exchange>>,
-    mappings: BTreeMap<String, Vec<String>>,
+    mappings: BTreeMap<String, Vec<Symbol>>, //parsed once when the feed is loaded
+    legs: BTreeMap<String, Vec<String>>,     //exchange -> symbols to fetch
+    max_age: BTreeMap<String, u64>,          //exchange -> ms
+    strategy: Box<dyn Strategy>,
+    spread_policy: SpreadPolicy,
+    resilience: ResilienceConfig,
+    streams: StreamHub,
+    streamed: BTreeSet<String>, //exchanges reading tickers from streams
     s_state: SharedState,
 }
 
-pub fn new(mappings: BTreeMap<String, Vec<String>>, _s_state: SharedState) -> Aggregator {
+// PriceResult is the aggregated price of a feed, with how dispersed its sources were
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PriceResult {
+    pub price: u128,       //scaled by PRECESIONS_REPRESENT
+    pub std_dev: f64,      //volume weighted standard deviation of prices around the aggregated one
+    pub sources: usize,    //pairs aggregated
+    pub outliers: usize,   //pairs removed as outliers
+    pub total_volume: f64, //of pairs aggregated, in the quote currency of the feed
+}
+
+impl PriceResult {
+    // dispersion is the standard deviation relative to the price
+    pub fn dispersion(&self) -> f64 {
+        if self.price == 0 {
+            return 0.0;
+        }
+        self.std_dev * PRECESIONS_REPRESENT / self.price as f64
+    }
+}
+
+// SpreadPolicy decides how bid and ask of pairs are used, pairs without them are left as they are
+pub struct SpreadPolicy {
+    price_source: PriceSource,
+    max_spread: Option<f64>,
+    spread_weighted: bool,
+}
+
+impl SpreadPolicy {
+    // apply rejects a pair whose spread is too wide, otherwise prices it by the mid price if configured,
+    // and scales its volume by the confidence of the quote
+    fn apply(&self, pair: &PairInfo) -> Result<PairInfo, String> {
+        let mut pair = pair.clone();
+        if let (Some(spread), Some(max_spread)) = (fetcher::spread(&pair), self.max_spread) {
+            if spread > max_spread {
+                return Err(format!("spread {:.6} wider than {}", spread, max_spread));
+            }
+            if self.spread_weighted {
+                pair.volume = pair.volume * (1.0 - spread / max_spread);
+            }
+        }
+        if self.price_source == PriceSource::Mid {
+            if let Some(mid) = fetcher::mid_price(&pair) {
+                pair.price = mid;
+            }
+        }
+        Ok(pair)
+    }
+}
+
+pub fn new(
+    cfg: &Config,
+    feed: &FeedConfig,
+    http: HttpClient,
+    streams: &StreamHub,
+    _s_state: SharedState,
+) -> Aggregator {
+    // mappings and legs are keyed by canonical names, and disabled exchanges are left out
+    let mut mappings = BTreeMap::<String, Vec<Symbol>>::new();
+    for (ex_name, symbols) in &feed.mappings {
+        let ex_name = canonical_name(ex_name);
+        if !cfg.exchange_enabled(&ex_name) {
+            warn!(
+                "{} is disabled, its mappings in {} are ignored",
+                ex_name, feed.coin_name
+            );
+            continue;
+        }
+        let parsed = mappings.entry(ex_name.clone()).or_default();
+        for symbol in symbols {
+            // invalid mappings are rejected when loading config
+            match expression::parse_symbol(symbol) {
+                Ok(symbol) => parsed.push(symbol),
+                Err(err) => warn!("{} of {} ignored: {:?}", symbol, ex_name, err),
+            }
+        }
+    }
+    let mut legs = BTreeMap::<String, Vec<String>>::new();
+    for (ex_name, symbols) in expression::legs_by_exchange(&mappings) {
+        let ex_name = canonical_name(&ex_name);
+        if !cfg.exchange_enabled(&ex_name) {
+            continue;
+        }
+        let merged = legs.entry(ex_name).or_default();
+        for symbol in symbols {
+            if !merged.contains(&symbol) {
+                merged.push(symbol);
+            }
+        }
+    }
     let mut agg = Aggregator {
+        coin_name: feed.coin_name.clone(),
+        asset: feed.asset.clone(),
+        min_volume: feed.min_volume,
         data_sources: BTreeMap::new(),
+        max_age: mappings
+            .keys()
+            .map(|ex_name| (ex_name.clone(), cfg.max_age(ex_name)))
+            .collect(),
         mappings: mappings,
+        legs: legs,
+        strategy: new_strategy(feed),
+        spread_policy: SpreadPolicy {
+            price_source: feed.price_source.clone(),
+            max_spread: feed.max_spread,
+            spread_weighted: feed.spread_weighted,
+        },
+        resilience: cfg.resilience.clone(),
+        streams: streams.clone(),
+        streamed: BTreeSet::new(),
         s_state: _s_state,
     };
-    agg.data_sources
-        .insert(binance::NAME.into(), Box::new(Binance::default()));
-    agg.data_sources
-        .insert(coinbase::NAME.into(), Box::new(Coinbase::default()));
-    agg.data_sources
-        .insert(uniswapv3::NAME.into(), Box::new(UniswapV3::default()));
-    agg.data_sources
-        .insert(uniswapv2::NAME.into(), Box::new(UniswapV2::default()));
-    agg.data_sources
-        .insert(ftx::NAME.into(), Box::new(Ftx::default()));
-    agg.data_sources
-        .insert(kucoin::NAME.into(), Box::new(Kucoin::default()));
-    agg.data_sources
-        .insert(okex::NAME.into(), Box::new(OkEx::default()));
-    agg.data_sources
-        .insert(sushiswap::NAME.into(), Box::new(Sushiswap::default()));
+    let ctx = Context {
+        cfg: cfg,
+        legs: &agg.legs,
+        coin: feed.asset(),
+        http: http.clone(),
+        chain: ChainReader {
+            rpc_url: cfg.dex_rpc_url.clone(),
+            block: cfg.dex_block,
+            volumes: Default::default(),
+        },
+        streams: streams,
+    };
+    let mut data_sources = BTreeMap::<String, Box<dyn Exchange>>::new();
+    for registration in EXCHANGES {
+        if cfg.exchange_enabled(registration.name) {
+            data_sources.insert(registration.name.into(), (registration.build)(&ctx));
+            if registration.stream.is_some() && ctx.stream(registration.name).is_some() {
+                agg.streamed.insert(registration.name.into());
+            }
+        }
+    }
+    // venues are validated when config is loaded
+    for (name, venue) in &cfg.venues {
+        if !cfg.exchange_enabled(name) {
+            continue;
+        }
+        match rest::new(name, venue, http.clone()) {
+            Ok(exchange) => {
+                data_sources.insert(name.clone(), Box::new(exchange));
+            }
+            Err(err) => warn!("venue {} ignored: {}", name, err),
+        }
+    }
+    agg.data_sources = data_sources;
     agg
 }
 
+// start_streams subscribes tickers of all feeds in background, one stream for each exchange,
+// called once after all feeds claimed their streams
+pub fn start_streams(streams: &StreamHub, http: &HttpClient) {
+    for registration in EXCHANGES {
+        if let Some(build) = registration.stream {
+            streams.start(build(http));
+        }
+    }
+}
+
 #[derive(Debug, Display)]
 pub enum AggError {
     NoEnoughVolumes(f64),
     NoPairs(String),
+    NoDepth(String),
 }
 impl std::error::Error for AggError {}
 
 impl Aggregator {
-    pub async fn get_price(&self) -> Result<u128, Box<dyn Error + Send + Sync>> {
+    // register an exchange implemented outside this crate, or replace a built-in one, before the feed starts
+    pub fn register(&mut self, name: &str, exchange: Box<dyn Exchange>) {
+        let name = canonical_name(name);
+        self.streamed.remove(&name);
+        self.data_sources.insert(name, exchange);
+    }
+
+    // claim_streams adds symbols of exchanges still reading streams, once registration is final
+    pub fn claim_streams(&self) {
+        for ex_name in &self.streamed {
+            self.streams.claim(ex_name, &self.legs[ex_name]);
+        }
+    }
+
+    // validate fails if any exchange in mappings is neither registered nor disabled
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for ex_name in self.legs.keys() {
+            if !self.data_sources.contains_key(ex_name) {
+                return Err(format!(
+                    "unknown exchange {} in mappings of {}",
+                    ex_name, self.coin_name
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    // validate_sources checks symbols against their sources, e.g. a DEX pool must contain the base token
+    // of its leg, while sources which can not be reached are only warned as they may recover later
+    pub async fn validate_sources(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for (ex_name, symbols) in &self.legs {
+            let exchange = match self.data_sources.get(ex_name) {
+                Some(exchange) => exchange,
+                None => continue,
+            };
+            if let Err(err) = exchange.validate(symbols).await {
+                if err.is::<PoolError>() {
+                    return Err(format!(
+                        "invalid mappings of {} in {}: {:?}",
+                        ex_name, self.coin_name, err
+                    )
+                    .into());
+                }
+                warn!("failed to validate symbols of {}: {:?}", ex_name, err);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn get_price(&self) -> Result<PriceResult, Box<dyn Error + Send + Sync>> {
         let mut exchagne_names = Vec::<String>::new();
         let tasks = self
             .data_sources
             .iter()
-            .filter(|item| self.mappings.contains_key(item.0))
+            .filter(|item| self.legs.contains_key(item.0))
             .map(|(ex_name, exchange)| {
-                let symbols = self.mappings[ex_name].clone();
-                let result = exchange.get_pairs(symbols);
+                let symbols = self.legs[ex_name].clone();
+                let result = resilience::get_pairs(
+                    &self.resilience,
+                    &self.s_state,
+                    ex_name,
+                    exchange.as_ref(),
+                    symbols,
+                );
                 exchagne_names.push(ex_name.clone());
                 return result;
             });
         let all_exchanges: Vec<Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>>> =
             future::join_all(tasks).await;
-        let mut total_volume = 0 as f64;
-        let mut all_pairs = Vec::<&PairInfo>::new();
-        let mut offset: usize = 0;
-        for exchange in &all_exchanges {
-            match exchange {
-                Ok(pairs) => {
-                    info!("*** {} ***", exchagne_names[offset]);
-                    for pair in pairs {
-                        total_volume += pair.volume;
-                        all_pairs.push(pair);
-                        info!(
-                            " +--- {} -> {} vol:{}",
-                            pair.symbol, pair.price, pair.volume
-                        );
-                    }
+        let mut fetched = BTreeMap::<&String, Vec<PairInfo>>::new();
+        for (legs, ex_name) in all_exchanges.into_iter().zip(&exchagne_names) {
+            match legs {
+                Ok(legs) => {
+                    fetched.insert(ex_name, legs);
                 }
                 Err(err) => {
-                    warn!("{}", err)
+                    warn!("{}: {}", ex_name, err)
                 }
             }
-            offset += 1;
         }
-        if total_volume < 1.0 {
-            return Err(Box::new(AggError::NoEnoughVolumes(total_volume)));
+        // legs of all exchanges, named as `@exchange:symbol` by any alias, for cross-exchange expressions
+        let mut cross_legs = Vec::<PairInfo>::new();
+        for (ex_name, legs) in &fetched {
+            let mut names = vec![ex_name.as_str()];
+            if let Some(registration) = EXCHANGES.iter().find(|r| r.name == ex_name.as_str()) {
+                names.extend(registration.aliases);
+            }
+            for name in names {
+                for leg in legs {
+                    let mut leg = leg.clone();
+                    leg.symbol = format!("@{}:{}", name, leg.symbol);
+                    cross_legs.push(leg);
+                }
+            }
+        }
+        // evaluate configured symbols by legs fetched
+        let mut skipped = Vec::<SkippedSymbol>::new();
+        let mut exchagne_names = Vec::<String>::new();
+        let mut all_exchanges = Vec::<Vec<PairInfo>>::new();
+        for (ex_name, symbols) in &self.mappings {
+            let mut legs = match fetched.get(ex_name) {
+                Some(legs) => legs.clone(),
+                None if self.legs.contains_key(ex_name) => continue, //failed to fetch
+                None => Vec::new(), //all legs are from other exchanges
+            };
+            legs.extend(cross_legs.iter().cloned());
+            let (pairs, failed) = expression::reduce_symbols(symbols, &legs);
+            for (symbol, err) in failed {
+                warn!("{} of {} skipped: {:?}", symbol, ex_name, err);
+                skipped.push(SkippedSymbol {
+                    exchange: ex_name.clone(),
+                    symbol: symbol,
+                    reason: format!("{:?}", err),
+                });
+            }
+            exchagne_names.push(ex_name.clone());
+            all_exchanges.push(pairs);
+        }
+        let mut base_volume = 0 as f64;
+        let mut accepted = Vec::<PairInfo>::new();
+        let mut rejected = Vec::<RejectedPair>::new();
+        let mut offset: usize = 0;
+        let now = utils::timestamp();
+        for pairs in &all_exchanges {
+            let ex_name = &exchagne_names[offset];
+            info!("*** {} ***", ex_name);
+            for pair in pairs {
+                let age = now.saturating_sub(pair.timestamp);
+                if age > self.max_age[ex_name] {
+                    warn!("stale pair skipped, {}ms old: {:?}", age, pair);
+                    rejected.push(RejectedPair {
+                        pair: pair.clone(),
+                        reason: format!("stale, {}ms old", age),
+                    });
+                    continue;
+                }
+                let normalized = self
+                    .spread_policy
+                    .apply(pair)
+                    .and_then(|pair| normalize_volume(&pair, self.asset.as_deref()));
+                let pair = match normalized {
+                    Ok(pair) => pair,
+                    Err(reason) => {
+                        warn!("pair rejected, {}: {:?}", reason, pair);
+                        rejected.push(RejectedPair {
+                            pair: pair.clone(),
+                            reason: reason,
+                        });
+                        continue;
+                    }
+                };
+                info!(
+                    " +--- {} -> {} vol:{}",
+                    pair.symbol, pair.price, pair.volume
+                );
+                base_volume += base_volume_of(&pair);
+                accepted.push(pair);
+            }
+            offset += 1;
         }
         {
             let monitor_pairs = &mut self.s_state.lock().unwrap().exchange_pairs;
-            monitor_pairs.clear();
-            for p in all_pairs.clone() {
-                monitor_pairs.push(p.clone());
+            monitor_pairs.insert(
+                self.coin_name.clone(),
+                PairsSnapshot {
+                    accepted: accepted.clone(),
+                    rejected: rejected,
+                    skipped: skipped,
+                },
+            );
+        }
+        if base_volume < self.min_volume {
+            return Err(Box::new(AggError::NoEnoughVolumes(base_volume)));
+        }
+        let result = calc_weighted_price(accepted.iter().collect(), self.strategy.as_ref())?;
+        self.s_state
+            .lock()
+            .unwrap()
+            .prices
+            .insert(self.coin_name.clone(), result.clone());
+        Ok(result)
+    }
+}
+
+// normalize_volume converts the volume of a pair to its notional in the quote currency of the feed,
+// so that pairs of different venues and units are weighted alike; a base volume counted in another
+// asset than the one of the feed is refused, while wrapped tokens like WBTC are taken as BTC
+fn normalize_volume(pair: &PairInfo, asset: Option<&str>) -> Result<PairInfo, String> {
+    let mut pair = pair.clone();
+    if pair.volume_unit.side == Side::Base {
+        if let (Some(counted), Some(asset)) = (&pair.volume_unit.asset, asset) {
+            if !pool::same_asset(counted, asset) {
+                return Err(format!("volume counted in {}, not in {}", counted, asset));
+            }
+        }
+        pair.volume = pair.volume * pair.price;
+    }
+    pair.volume_unit = VolumeUnit::quote(None);
+    Ok(pair)
+}
+
+// volume of a normalized pair back in base units, to compare with min_volume
+fn base_volume_of(pair: &PairInfo) -> f64 {
+    if pair.price > 0.0 {
+        pair.volume / pair.price
+    } else {
+        0.0
+    }
+}
+
+// Strategy calculates the price of a feed from pairs which survive outliers removal
+pub trait Strategy: Send + Sync {
+    fn aggregate(&self, pairs: &Vec<&PairInfo>) -> Result<f64, Box<dyn Error + Send + Sync>>;
+}
+
+pub fn new_strategy(feed: &FeedConfig) -> Box<dyn Strategy> {
+    match feed.strategy {
+        flags::Strategy::VolumeWeightedMean => Box::new(VolumeWeightedMean {}),
+        flags::Strategy::VolumeWeightedMedian => Box::new(VolumeWeightedMedian {}),
+        flags::Strategy::LiquidityWeighted => Box::new(LiquidityWeighted {}),
+        flags::Strategy::TrimmedMean => Box::new(TrimmedMean {
+            ratio: feed.trim_ratio,
+        }),
+        flags::Strategy::Twap => Box::new(Twap::new(feed.twap_samples)),
+    }
+}
+
+pub struct VolumeWeightedMean {}
+
+impl Strategy for VolumeWeightedMean {
+    fn aggregate(&self, pairs: &Vec<&PairInfo>) -> Result<f64, Box<dyn Error + Send + Sync>> {
+        weighted_mean(pairs.iter().map(|p| (p.price, p.volume)))
+    }
+}
+
+// price where half of the volume is traded below, and half above
+pub struct VolumeWeightedMedian {}
+
+impl Strategy for VolumeWeightedMedian {
+    fn aggregate(&self, pairs: &Vec<&PairInfo>) -> Result<f64, Box<dyn Error + Send + Sync>> {
+        let sorted = sort_by_price(pairs);
+        let total_volume: f64 = sorted.iter().map(|p| p.volume).sum();
+        let mut acc_volume = 0.0;
+        for pair in &sorted {
+            acc_volume += pair.volume;
+            if acc_volume >= total_volume / 2.0 {
+                return Ok(pair.price);
+            }
+        }
+        Err(Box::new(AggError::NoEnoughVolumes(total_volume)))
+    }
+}
+
+// weighted by depth of markets instead of volume, pairs without depth are ignored
+pub struct LiquidityWeighted {}
+
+impl Strategy for LiquidityWeighted {
+    fn aggregate(&self, pairs: &Vec<&PairInfo>) -> Result<f64, Box<dyn Error + Send + Sync>> {
+        let with_depth: Vec<(f64, f64)> = pairs
+            .iter()
+            .filter_map(|p| p.depth.map(|depth| (p.price, depth)))
+            .collect();
+        if with_depth.is_empty() {
+            return Err(Box::new(AggError::NoDepth(format!(
+                "{} pairs",
+                pairs.len()
+            ))));
+        }
+        weighted_mean(with_depth.into_iter())
+    }
+}
+
+// volume weighted mean, after trimming the given ratio of volume from both sides of the price range
+pub struct TrimmedMean {
+    pub ratio: f64,
+}
+
+impl Strategy for TrimmedMean {
+    fn aggregate(&self, pairs: &Vec<&PairInfo>) -> Result<f64, Box<dyn Error + Send + Sync>> {
+        let sorted = sort_by_price(pairs);
+        let total_volume: f64 = sorted.iter().map(|p| p.volume).sum();
+        let lower = total_volume * self.ratio;
+        let upper = total_volume - lower;
+        let mut acc_volume = 0.0;
+        let mut kept = Vec::<(f64, f64)>::new();
+        for pair in &sorted {
+            // only the part of volume in [lower, upper] is kept
+            let start = acc_volume;
+            acc_volume += pair.volume;
+            let volume = acc_volume.min(upper) - start.max(lower);
+            if volume > 0.0 {
+                kept.push((pair.price, volume));
             }
         }
-        calc_weighted_price(all_pairs)
+        weighted_mean(kept.into_iter())
     }
 }
 
+// time weighted average of the latest volume weighted means
+pub struct Twap {
+    samples: usize,
+    history: Mutex<VecDeque<(u64, f64)>>, //timestamp in ms, price
+}
+
+impl Twap {
+    pub fn new(samples: usize) -> Twap {
+        Twap {
+            samples: samples,
+            history: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // add a price sample observed at the timestamp, and average the samples kept
+    pub fn sample(&self, timestamp: u64, price: f64) -> f64 {
+        let mut history = self.history.lock().unwrap();
+        history.push_back((timestamp, price));
+        while history.len() > self.samples {
+            history.pop_front();
+        }
+        // trapezoidal integration of prices over the time covered by samples
+        let mut area = 0.0;
+        for (prev, cur) in history.iter().zip(history.iter().skip(1)) {
+            area += (prev.1 + cur.1) / 2.0 * (cur.0 - prev.0) as f64;
+        }
+        let period = (history.back().unwrap().0 - history.front().unwrap().0) as f64;
+        if period <= 0.0 {
+            return price;
+        }
+        area / period
+    }
+}
+
+impl Strategy for Twap {
+    fn aggregate(&self, pairs: &Vec<&PairInfo>) -> Result<f64, Box<dyn Error + Send + Sync>> {
+        let price = VolumeWeightedMean {}.aggregate(pairs)?;
+        Ok(self.sample(utils::timestamp(), price))
+    }
+}
+
+fn sort_by_price<'a>(pairs: &Vec<&'a PairInfo>) -> Vec<&'a PairInfo> {
+    let mut sorted = pairs.clone();
+    sorted.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
+    sorted
+}
+
+// mean of prices weighted by the given weights
+fn weighted_mean(
+    items: impl Iterator<Item = (f64, f64)>,
+) -> Result<f64, Box<dyn Error + Send + Sync>> {
+    let mut total_weight = 0.0;
+    let mut sum = 0.0;
+    for (price, weight) in items {
+        debug!(" ++ {} x {}", price, weight);
+        total_weight += weight;
+        sum += price * weight;
+    }
+    if total_weight <= 0.0 {
+        return Err(Box::new(AggError::NoEnoughVolumes(total_weight)));
+    }
+    Ok(sum / total_weight)
+}
+
 fn remove_outliers(mut all_pairs: Vec<&PairInfo>) -> Vec<&PairInfo> {
     let mut result = Vec::<&PairInfo>::new();
     all_pairs.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
@@ -132,34 +824,308 @@ fn remove_outliers(mut all_pairs: Vec<&PairInfo>) -> Vec<&PairInfo> {
 
 fn calc_weighted_price(
     all_pairs_original: Vec<&PairInfo>,
-) -> Result<u128, Box<dyn Error + Send + Sync>> {
+    strategy: &dyn Strategy,
+) -> Result<PriceResult, Box<dyn Error + Send + Sync>> {
     debug!("pairs count:{}", all_pairs_original.len());
+    let count = all_pairs_original.len();
     let all_pairs = remove_outliers(all_pairs_original);
     debug!("oufter remove outliers, pairs count:{}", all_pairs.len());
-    let mut avg_price = 0.0 as f64;
-    let total_volume = all_pairs
-        .iter()
-        .map(|a| a.volume)
-        .reduce(|a, b| a + b)
-        .unwrap();
-    for pair in all_pairs {
-        debug!(" ++ {}", pair.price);
-        avg_price += pair.price as f64 * pair.volume / total_volume;
-    }
+    let avg_price = strategy.aggregate(&all_pairs)?;
     debug!("avg : {}", avg_price);
-    Ok((avg_price * PRECESIONS_REPRESENT) as u128)
+    let total_volume: f64 = all_pairs.iter().map(|p| p.volume).sum();
+    Ok(PriceResult {
+        price: (avg_price * PRECESIONS_REPRESENT) as u128,
+        std_dev: weighted_std_dev(&all_pairs, avg_price),
+        sources: all_pairs.len(),
+        outliers: count - all_pairs.len(),
+        total_volume: total_volume,
+    })
+}
+
+// weighted_std_dev measures how far prices of pairs are from the aggregated price, weighted by volume
+fn weighted_std_dev(pairs: &Vec<&PairInfo>, price: f64) -> f64 {
+    let total_volume: f64 = pairs.iter().map(|p| p.volume).sum();
+    if total_volume <= 0.0 {
+        return 0.0;
+    }
+    let variance: f64 = pairs
+        .iter()
+        .map(|p| p.volume * (p.price - price).powi(2))
+        .sum::<f64>()
+        / total_volume;
+    variance.sqrt()
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::fetcher::aggregator;
+    use crate::fetcher::mock;
     use crate::flags;
     use crate::processor::web::SharedState;
     #[tokio::test]
     async fn test_agg() {
-        let cfg = flags::Config::new("./config/node.yaml").unwrap();
-        let agg = aggregator::new(cfg.mappings, SharedState::default());
-        let weighted_price = agg.get_price().await.unwrap();
-        println!("weighted price:{}", weighted_price);
+        let mut cfg = flags::Config::new("./config/node.yaml").unwrap();
+        // fixtures were recorded long ago
+        cfg.default_max_age = 100 * 365 * 24 * 3600;
+        cfg.max_age.clear();
+        let url = mock::serve(mock::fixtures()).await;
+        let mut agg = aggregator::new(
+            &cfg,
+            &cfg.feeds[0],
+            HttpClient::default(),
+            &StreamHub::new(0),
+            SharedState::default(),
+        );
+        agg.register(
+            binance::NAME,
+            Box::new(Binance {
+                base_url: url.clone(),
+                ..Default::default()
+            }),
+        );
+        agg.register(
+            coinbase::NAME,
+            Box::new(Coinbase {
+                base_url: url.clone(),
+                ..Default::default()
+            }),
+        );
+        agg.register(
+            kraken::NAME,
+            Box::new(kraken::Kraken {
+                base_url: url.clone(),
+                ..Default::default()
+            }),
+        );
+        agg.register(
+            bitfinex::NAME,
+            Box::new(bitfinex::Bitfinex {
+                base_url: url.clone(),
+                ..Default::default()
+            }),
+        );
+        agg.register(
+            bybit::NAME,
+            Box::new(bybit::Bybit {
+                base_url: url.clone(),
+                ..Default::default()
+            }),
+        );
+        agg.register(
+            gateio::NAME,
+            Box::new(gateio::Gateio {
+                base_url: url.clone(),
+                ..Default::default()
+            }),
+        );
+        agg.register(
+            huobi::NAME,
+            Box::new(huobi::Huobi {
+                base_url: url.clone(),
+                ..Default::default()
+            }),
+        );
+        agg.register(
+            kucoin::NAME,
+            Box::new(Kucoin {
+                base_url: url.clone(),
+                ..Default::default()
+            }),
+        );
+        agg.register(
+            okex::NAME,
+            Box::new(OkEx {
+                base_url: url.clone(),
+                ..Default::default()
+            }),
+        );
+        agg.register(
+            uniswapv2::NAME,
+            Box::new(UniswapV2 {
+                subgraph_url: format!("{}/uniswap-v2", url),
+                coin: cfg.feeds[0].asset().into(),
+                ..Default::default()
+            }),
+        );
+        agg.register(
+            uniswapv3::NAME,
+            Box::new(UniswapV3 {
+                subgraph_url: format!("{}/uniswap-v3", url),
+                coin: cfg.feeds[0].asset().into(),
+                ..Default::default()
+            }),
+        );
+        agg.register(
+            sushiswap::NAME,
+            Box::new(Sushiswap {
+                subgraph_url: format!("{}/sushiswap", url),
+                coin: cfg.feeds[0].asset().into(),
+                ..Default::default()
+            }),
+        );
+        agg.validate_sources().await.unwrap();
+        let result = agg.get_price().await.unwrap();
+        println!("weighted price:{:?}", result);
+        let price = result.price as f64 / PRECESIONS_REPRESENT;
+        assert!(price > 23000.0 && price < 23020.0);
+        assert_eq!(result.sources + result.outliers, 20);
+        assert!(result.std_dev > 0.0 && result.std_dev < 20.0);
+        let s_state = agg.s_state.lock().unwrap();
+        let snapshot = &s_state.exchange_pairs[&cfg.feeds[0].coin_name];
+        assert_eq!(snapshot.accepted.len(), 20);
+        assert_eq!(snapshot.rejected.len(), 0);
+        assert_eq!(s_state.prices[&cfg.feeds[0].coin_name].price, result.price);
+    }
+
+    #[tokio::test]
+    async fn test_registry() {
+        let mut cfg = flags::Config::new("./config/node.yaml").unwrap();
+        cfg.exchanges.insert("kraken".into(), false);
+        let mut feed = cfg.feeds[0].clone();
+        feed.mappings = BTreeMap::from([
+            ("okx".into(), vec!["BTC-USDT".into()]),
+            ("kraken".into(), vec!["XXBTZUSD".into()]),
+            ("tiex".into(), vec!["BTC-USDT div @okx:USDC-USDT".into()]),
+        ]);
+        cfg.streaming = true;
+        let streams = StreamHub::new(0);
+        let mut agg = aggregator::new(
+            &cfg,
+            &feed,
+            HttpClient::default(),
+            &streams,
+            SharedState::default(),
+        );
+        assert_eq!(agg.legs["okex"], vec!["BTC-USDT", "USDC-USDT"]);
+        assert!(agg.streamed.contains("okex"));
+        agg.register("okx", Box::new(OkEx::default()));
+        assert!(!agg.streamed.contains("okex"));
+        assert!(!agg.legs.contains_key("kraken"));
+        assert!(!agg.data_sources.contains_key("kraken"));
+        let err = agg.validate().unwrap_err();
+        assert!(err.to_string().contains("unknown exchange tiex"));
+        agg.register("tiex", Box::new(Binance::default()));
+        assert!(agg.validate().is_ok());
+    }
+
+    fn pair(price: f64, volume: f64, depth: Option<f64>) -> PairInfo {
+        PairInfo {
+            symbol: "BTC-USDT".into(),
+            price: price,
+            volume: volume,
+            timestamp: 0,
+            exchange: "tiex".into(),
+            depth: depth,
+            volume_unit: VolumeUnit::base(None),
+            bid: None,
+            ask: None,
+        }
+    }
+
+    #[test]
+    fn test_strategies() {
+        let pairs = vec![
+            pair(100.0, 1.0, Some(10.0)),
+            pair(101.0, 2.0, None),
+            pair(102.0, 3.0, Some(30.0)),
+            pair(110.0, 4.0, None),
+        ];
+        let pairs: Vec<&PairInfo> = pairs.iter().collect();
+        let price = VolumeWeightedMean {}.aggregate(&pairs).unwrap();
+        assert!((price - 104.8).abs() < 1e-9);
+        let price = VolumeWeightedMedian {}.aggregate(&pairs).unwrap();
+        assert_eq!(price, 102.0);
+        let price = LiquidityWeighted {}.aggregate(&pairs).unwrap();
+        assert!((price - 101.5).abs() < 1e-9);
+        // 2 of 10 volume trimmed from each side: 1@101 + 3@102 + 2@110
+        let price = TrimmedMean { ratio: 0.2 }.aggregate(&pairs).unwrap();
+        assert!((price - 627.0 / 6.0).abs() < 1e-9);
+        let twap = Twap::new(3);
+        assert_eq!(twap.sample(1000, 100.0), 100.0);
+        assert_eq!(twap.sample(2000, 102.0), 101.0);
+        assert_eq!(twap.sample(3000, 100.0), 101.0);
+        // the oldest sample is dropped
+        assert_eq!(twap.sample(5000, 96.0), 99.0);
+        let no_depth = vec![pair(100.0, 1.0, None)];
+        let no_depth: Vec<&PairInfo> = no_depth.iter().collect();
+        assert!(LiquidityWeighted {}.aggregate(&no_depth).is_err());
+    }
+
+    #[test]
+    fn test_spread_policy() {
+        let mut quoted = pair(100.0, 2.0, None);
+        quoted.bid = Some(99.0);
+        quoted.ask = Some(101.0);
+        let policy = SpreadPolicy {
+            price_source: PriceSource::Mid,
+            max_spread: Some(0.04),
+            spread_weighted: true,
+        };
+        let mut last = pair(100.5, 2.0, None);
+        last.bid = Some(99.8);
+        last.ask = Some(100.2);
+        let applied = policy.apply(&last).unwrap();
+        assert_eq!(applied.price, 100.0);
+        assert!((applied.volume - 2.0 * 0.9).abs() < 1e-9);
+        assert!((policy.apply(&quoted).unwrap().volume - 1.0).abs() < 1e-9);
+        // 2% wide spread is rejected by a threshold of 1%
+        let strict = SpreadPolicy {
+            max_spread: Some(0.01),
+            ..policy
+        };
+        assert!(strict.apply(&quoted).is_err());
+        assert_eq!(strict.apply(&pair(100.0, 2.0, None)).unwrap().price, 100.0);
+    }
+
+    #[test]
+    fn test_dispersion() {
+        let pairs = vec![
+            pair(99.0, 1.0, None),
+            pair(101.0, 1.0, None),
+            pair(100.0, 1.0, None),
+            pair(100.0, 1.0, None),
+            pair(150.0, 1.0, None),
+        ];
+        let result = calc_weighted_price(pairs.iter().collect(), &VolumeWeightedMean {}).unwrap();
+        assert_eq!(result.price, 100 * PRECESIONS_REPRESENT as u128);
+        assert_eq!(result.sources, 4);
+        assert_eq!(result.outliers, 1);
+        assert_eq!(result.total_volume, 4.0);
+        // sqrt((1 * 1 + 1 * 1) / 4)
+        assert!((result.std_dev - 0.5f64.sqrt()).abs() < 1e-9);
+        assert!((result.dispersion() - 0.5f64.sqrt() / 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_normalize_volume() {
+        let base = normalize_volume(&pair(100.0, 2.0, None), None).unwrap();
+        assert_eq!(base.volume, 200.0);
+        assert_eq!(base.volume_unit, VolumeUnit::quote(None));
+        let mut quote = pair(100.0, 200.0, None);
+        quote.volume_unit = VolumeUnit::quote(None);
+        assert_eq!(normalize_volume(&quote, None).unwrap().volume, 200.0);
+        // base volumes are checked against the asset of the feed, wrapped or renamed ones included
+        let counted_in = |symbol: &str| {
+            let mut pair = pair(100.0, 2.0, None);
+            pair.volume_unit = VolumeUnit::of_pair(symbol);
+            normalize_volume(&pair, Some("BTC"))
+        };
+        assert_eq!(counted_in("BTC-USDT").unwrap().volume, 200.0);
+        assert!(counted_in("WBTCUSDT").is_ok());
+        assert!(counted_in("XBT/USD").is_ok());
+        assert_eq!(
+            counted_in("ETHUSDT").unwrap_err(),
+            "volume counted in ETH, not in BTC"
+        );
+        // quote volumes and unknown assets are taken as they are
+        quote.volume_unit = VolumeUnit::quote(Some("USDC"));
+        assert!(normalize_volume(&quote, Some("BTC")).is_ok());
+        assert!(counted_in("UNKNOWNPAIR").is_ok());
+        // min_volume is compared in base units, not in quote notional
+        let normalized = counted_in("BTC-USDT").unwrap();
+        assert_eq!(base_volume_of(&normalized), 2.0);
+        let normalized = normalize_volume(&quote, None).unwrap();
+        assert_eq!(base_volume_of(&normalized), quote.volume / quote.price);
     }
 }
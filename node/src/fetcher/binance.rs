@@ -1,13 +1,13 @@
-use super::expression;
+use super::http::HttpClient;
+use super::stream::{self, StreamSpec, TickerCache};
 use super::Exchange;
-use crate::fetcher::PairInfo;
+use crate::fetcher::{PairInfo, VolumeUnit};
 use async_trait::async_trait;
 use eyre::Result;
-use reqwest::ClientBuilder;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use serde_json::Value;
 use std::error::Error;
-use std::time::Duration;
 pub type Piars = Vec<Pair>;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -36,10 +36,25 @@ pub struct Pair {
     pub count: i64,
 }
 
-#[derive(Default, Clone)]
-pub struct Binance {}
+#[derive(Clone)]
+pub struct Binance {
+    pub base_url: String,
+    pub http: HttpClient,
+    pub stream: Option<TickerCache>, //read tickers from websocket if specified
+}
+
+impl Default for Binance {
+    fn default() -> Self {
+        Binance {
+            base_url: BASE_URL.into(),
+            http: HttpClient::default(),
+            stream: None,
+        }
+    }
+}
 
 pub const NAME: &str = "binance";
+pub const BASE_URL: &str = "https://api.binance.com";
 
 #[async_trait]
 impl Exchange for Binance {
@@ -47,40 +62,130 @@ impl Exchange for Binance {
         &self,
         symbols: Vec<String>,
     ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
-        let request_url = format!("https://api.binance.com/api/v3/ticker/24hr");
-        let timeout = Duration::new(5, 0);
-        let client = ClientBuilder::new().timeout(timeout).gzip(true).build()?;
-        let response = client.get(&request_url).send().await?;
+        if let Some(cache) = &self.stream {
+            if let Some(crawl_result) = cache.get_all(&symbols) {
+                return Ok(crawl_result);
+            }
+        }
+        let request_url = format!("{}/api/v3/ticker/24hr", self.base_url);
+        let response = self.http.send(self.http.get(&request_url)).await?;
         let pair_list: Vec<Pair> = response.json().await?;
         let mut crawl_result = Vec::<PairInfo>::new();
-        let expand_pairs = expression::expand_symbols(&symbols);
         for pair in &pair_list {
-            if symbols.contains(&pair.symbol) || expand_pairs.contains(&pair.symbol) {
+            if symbols.contains(&pair.symbol) {
                 crawl_result.push(PairInfo {
                     symbol: pair.symbol.clone(),
                     price: pair.last_price.parse::<f64>()?,
                     volume: pair.volume.parse::<f64>()?,
                     timestamp: pair.close_time as u64,
                     exchange: NAME.to_string(),
+                    depth: None,
+                    volume_unit: VolumeUnit::of_pair(&pair.symbol),
+                    bid: pair.bid_price.parse::<f64>().ok(),
+                    ask: pair.ask_price.parse::<f64>().ok(),
                 });
             }
         }
-        let result = expression::reduce_symbols(&symbols, &crawl_result);
-        return Ok(result);
+        return Ok(crawl_result);
+    }
+}
+
+// Ticker streams of all symbols, combined in one connection
+pub struct TickerStream {}
+
+#[async_trait]
+impl StreamSpec for TickerStream {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    async fn endpoint(
+        &self,
+        symbols: &Vec<String>,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let streams: Vec<String> = symbols
+            .iter()
+            .map(|symbol| format!("{}@ticker", symbol.to_lowercase()))
+            .collect();
+        Ok(format!(
+            "wss://stream.binance.com:9443/stream?streams={}",
+            streams.join("/")
+        ))
+    }
+
+    fn subscribe_messages(&self, _symbols: &Vec<String>) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn parse(&self, msg: &str) -> Vec<PairInfo> {
+        let mut result = Vec::<PairInfo>::new();
+        let msg: Value = match serde_json::from_str(msg) {
+            Ok(msg) => msg,
+            Err(_) => return result,
+        };
+        let data = &msg["data"];
+        if let (Some(symbol), Some(price), Some(volume), Some(ts)) = (
+            data["s"].as_str(),
+            stream::value_f64(&data["c"]),
+            stream::value_f64(&data["v"]),
+            data["E"].as_u64(),
+        ) {
+            result.push(PairInfo {
+                symbol: symbol.into(),
+                price: price,
+                volume: volume,
+                timestamp: ts,
+                exchange: NAME.into(),
+                depth: None,
+                volume_unit: VolumeUnit::of_pair(&symbol),
+                bid: stream::value_f64(&data["b"]),
+                ask: stream::value_f64(&data["a"]),
+            });
+        }
+        result
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fetcher::expression;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
     #[tokio::test]
     async fn test_fetch() {
-        let binance = Binance::default();
-        let result = binance
-            .get_pairs(vec!["BTCUSDC".into(), "BTCUSDT".into()])
-            .await;
-        let result = result.unwrap();
+        let url = mock::serve(vec![mock::route(
+            "/api/v3/ticker/24hr",
+            StatusCode::OK,
+            include_str!("../../fixtures/binance/ticker_24hr.json"),
+        )])
+        .await;
+        let binance = Binance {
+            base_url: url,
+            ..Default::default()
+        };
+        let symbols =
+            expression::parse_symbols(&vec!["BTCUSDC".into(), "BTCUSDT div USDCUSDT".into()])
+                .unwrap();
+        let legs = binance
+            .get_pairs(expression::expand_symbols(&symbols))
+            .await
+            .unwrap();
+        let (result, _) = expression::reduce_symbols(&symbols, &legs);
         println!("{:?}", result);
         assert_eq!(result.len(), 2);
+        assert_eq!(result[0].price, 23008.55);
+        assert_eq!(result[1].symbol, "BTCUSDT div USDCUSDT");
+        assert!((result[1].price - 23010.12 / 1.0001).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let msg = r#"{"stream":"btcusdt@ticker","data":{"e":"24hrTicker","E":1660000000000,"s":"BTCUSDT","c":"23000.10","v":"1234.5"}}"#;
+        let result = TickerStream {}.parse(msg);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].symbol, "BTCUSDT");
+        assert_eq!(result[0].price, 23000.10);
+        assert_eq!(result[0].timestamp, 1660000000000);
     }
 }
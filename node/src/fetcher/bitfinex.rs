@@ -0,0 +1,97 @@
+use super::http::HttpClient;
+use super::stream;
+use super::Exchange;
+use crate::fetcher::{PairInfo, VolumeUnit};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::error::Error;
+
+// a ticker is an array of [SYMBOL, BID, BID_SIZE, ASK, ASK_SIZE, DAILY_CHANGE,
+// DAILY_CHANGE_RELATIVE, LAST_PRICE, VOLUME, HIGH, LOW]
+const SYMBOL: usize = 0;
+const BID: usize = 1;
+const ASK: usize = 3;
+const LAST_PRICE: usize = 7;
+const VOLUME: usize = 8; //of the last 24 hours, in base currency
+
+pub struct Bitfinex {
+    pub base_url: String,
+    pub http: HttpClient,
+}
+
+impl Default for Bitfinex {
+    fn default() -> Self {
+        Bitfinex {
+            base_url: BASE_URL.into(),
+            http: HttpClient::default(),
+        }
+    }
+}
+
+pub const NAME: &str = "bitfinex";
+pub const BASE_URL: &str = "https://api-pub.bitfinex.com";
+
+#[async_trait]
+impl Exchange for Bitfinex {
+    // symbols are trading pairs prefixed by `t`, e.g. tBTCUSD
+    async fn get_pairs(
+        &self,
+        symbols: Vec<String>,
+    ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+        let request_url = format!("{}/v2/tickers?symbols={}", self.base_url, symbols.join(","));
+        let response = self.http.send(self.http.get(&request_url)).await?;
+        let timestamp = super::server_time(&response).ok_or("no Date header in response")?;
+        let tickers: Vec<Vec<Value>> = response.json().await?;
+        let mut result = Vec::<PairInfo>::new();
+        for ticker in &tickers {
+            let symbol = match ticker.get(SYMBOL).and_then(|symbol| symbol.as_str()) {
+                Some(symbol) if symbols.iter().any(|s| s == symbol) => symbol,
+                _ => continue,
+            };
+            let field = |index: usize| ticker.get(index).and_then(stream::value_f64);
+            result.push(PairInfo {
+                symbol: symbol.into(),
+                price: field(LAST_PRICE).ok_or("price invalid")?,
+                volume: field(VOLUME).ok_or("volume invalid")?,
+                timestamp: timestamp,
+                exchange: NAME.into(),
+                depth: None,
+                volume_unit: VolumeUnit::of_pair(symbol.strip_prefix('t').unwrap_or(symbol)),
+                bid: field(BID),
+                ask: field(ASK),
+            });
+        }
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::expression;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
+    #[tokio::test]
+    async fn test_fetch() {
+        let url = mock::serve(vec![mock::route(
+            "/v2/tickers",
+            StatusCode::OK,
+            include_str!("../../fixtures/bitfinex/tickers.json"),
+        )])
+        .await;
+        let bitfinex = Bitfinex {
+            base_url: url,
+            ..Default::default()
+        };
+        let symbols = expression::parse_symbols(&vec!["tBTCUSD".into(), "tBTCUST".into()]).unwrap();
+        let legs = bitfinex
+            .get_pairs(expression::expand_symbols(&symbols))
+            .await
+            .unwrap();
+        let (result, _) = expression::reduce_symbols(&symbols, &legs);
+        println!("{:?}", result);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].price, 23007.5);
+        assert_eq!(result[1].volume, 512.25);
+    }
+}
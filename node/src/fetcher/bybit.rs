@@ -0,0 +1,115 @@
+use super::http::HttpClient;
+use super::Exchange;
+use crate::fetcher::{PairInfo, VolumeUnit};
+use async_trait::async_trait;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::error::Error;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub ret_code: i64,
+    pub ret_msg: String,
+    pub result: TickerList,
+    pub time: u64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TickerList {
+    pub category: String,
+    pub list: Vec<Pair>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Pair {
+    pub symbol: String,
+    pub bid1_price: String,
+    pub ask1_price: String,
+    pub last_price: String,
+    pub turnover24h: String, //in quote currency
+    pub volume24h: String,   //in base currency
+}
+
+pub struct Bybit {
+    pub base_url: String,
+    pub http: HttpClient,
+}
+
+impl Default for Bybit {
+    fn default() -> Self {
+        Bybit {
+            base_url: BASE_URL.into(),
+            http: HttpClient::default(),
+        }
+    }
+}
+
+pub const NAME: &str = "bybit";
+pub const BASE_URL: &str = "https://api.bybit.com";
+
+#[async_trait]
+impl Exchange for Bybit {
+    async fn get_pairs(
+        &self,
+        symbols: Vec<String>,
+    ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+        let request_url = format!("{}/v5/market/tickers?category=spot", self.base_url);
+        let response = self.http.send(self.http.get(&request_url)).await?;
+        let rsps: Response = response.json().await?;
+        if rsps.ret_code != 0 {
+            return Err(rsps.ret_msg.into());
+        }
+        let mut result = Vec::<PairInfo>::new();
+        for pair in &rsps.result.list {
+            if symbols.contains(&pair.symbol) {
+                result.push(PairInfo {
+                    symbol: pair.symbol.clone(),
+                    price: pair.last_price.parse::<f64>()?,
+                    volume: pair.volume24h.parse::<f64>()?,
+                    timestamp: rsps.time,
+                    exchange: NAME.into(),
+                    depth: None,
+                    volume_unit: VolumeUnit::of_pair(&pair.symbol),
+                    bid: pair.bid1_price.parse::<f64>().ok(),
+                    ask: pair.ask1_price.parse::<f64>().ok(),
+                });
+            }
+        }
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::expression;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
+    #[tokio::test]
+    async fn test_fetch() {
+        let url = mock::serve(vec![mock::route(
+            "/v5/market/tickers?category=spot",
+            StatusCode::OK,
+            include_str!("../../fixtures/bybit/tickers.json"),
+        )])
+        .await;
+        let bybit = Bybit {
+            base_url: url,
+            ..Default::default()
+        };
+        let symbols = expression::parse_symbols(&vec!["BTCUSDT".into()]).unwrap();
+        let legs = bybit
+            .get_pairs(expression::expand_symbols(&symbols))
+            .await
+            .unwrap();
+        let (result, _) = expression::reduce_symbols(&symbols, &legs);
+        println!("{:?}", result);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].price, 23009.15);
+        assert_eq!(result[0].volume, 1800.654321);
+        assert_eq!(result[0].timestamp, 1660000000000);
+    }
+}
@@ -1,13 +1,14 @@
-use super::expression;
+use super::http::HttpClient;
+use super::stream::{self, StreamSpec, TickerCache};
 use super::Exchange;
-use super::PairInfo;
+use super::{PairInfo, VolumeUnit};
 use async_trait::async_trait;
 use chrono::prelude::*;
-use reqwest::ClientBuilder;
+use futures::future;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use serde_json::{json, Value};
 use std::error::Error;
-use std::time::Duration;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -22,10 +23,25 @@ pub struct Pair {
     pub time: String,
 }
 
-#[derive(Default, Clone)]
-pub struct Coinbase {}
+#[derive(Clone)]
+pub struct Coinbase {
+    pub base_url: String,
+    pub http: HttpClient,
+    pub stream: Option<TickerCache>, //read tickers from websocket if specified
+}
+
+impl Default for Coinbase {
+    fn default() -> Self {
+        Coinbase {
+            base_url: BASE_URL.into(),
+            http: HttpClient::default(),
+            stream: None,
+        }
+    }
+}
 
 pub const NAME: &str = "coinbase";
+pub const BASE_URL: &str = "https://api.pro.coinbase.com";
 
 #[async_trait]
 impl Exchange for Coinbase {
@@ -33,46 +49,158 @@ impl Exchange for Coinbase {
         &self,
         symbols: Vec<String>,
     ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
-        let mut crawl_result = Vec::<PairInfo>::new();
-        for symbol in expression::expand_symbols(&symbols) {
-            let request_url = format!(
-                "https://api.pro.coinbase.com/products/{symbol}/ticker",
-                symbol = symbol
-            );
-            //println!("{}", request_url);
-            let timeout = Duration::new(5, 0);
-            let client = ClientBuilder::new().timeout(timeout).build()?;
-            let response = client
-                .get(&request_url)
-                .header("User-Agent", "ti-oracle")
-                .send()
-                .await?;
-            let pair: Pair = response.json().await?;
-            //println!("{}", pair.time.clone());
-            let timestamp = DateTime::parse_from_str(&pair.time, "%+")?.timestamp();
-            crawl_result.push(PairInfo {
-                symbol: symbol.clone(),
-                price: pair.price.parse::<f64>()?,
-                volume: pair.volume.parse::<f64>()?,
-                timestamp: timestamp as u64 * 1000,
-                exchange: NAME.into(),
-            });
+        if let Some(cache) = &self.stream {
+            if let Some(crawl_result) = cache.get_all(&symbols) {
+                return Ok(crawl_result);
+            }
         }
-        let result = expression::reduce_symbols(&symbols, &crawl_result);
-        return Ok(result);
+        // one ticker per request, so symbols are fetched concurrently
+        let requests = symbols.iter().map(|symbol| self.get_pair(symbol));
+        future::try_join_all(requests).await
+    }
+
+    fn requests(&self, symbols: &Vec<String>) -> usize {
+        symbols.len()
     }
 }
+
+impl Coinbase {
+    async fn get_pair(&self, symbol: &str) -> Result<PairInfo, Box<dyn Error + Send + Sync>> {
+        let request_url = format!(
+            "{base_url}/products/{symbol}/ticker",
+            base_url = self.base_url,
+            symbol = symbol
+        );
+        let response = self.http.send(self.http.get(&request_url)).await?;
+        let pair: Pair = response.json().await?;
+        let timestamp = DateTime::parse_from_str(&pair.time, "%+")?.timestamp_millis();
+        Ok(PairInfo {
+            symbol: symbol.into(),
+            price: pair.price.parse::<f64>()?,
+            volume: pair.volume.parse::<f64>()?,
+            timestamp: timestamp as u64,
+            exchange: NAME.into(),
+            depth: None,
+            volume_unit: VolumeUnit::of_pair(&symbol),
+            bid: pair.bid.parse::<f64>().ok(),
+            ask: pair.ask.parse::<f64>().ok(),
+        })
+    }
+}
+
+// Ticker channel, which is updated on every trade
+pub struct TickerStream {}
+
+#[async_trait]
+impl StreamSpec for TickerStream {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    async fn endpoint(
+        &self,
+        _symbols: &Vec<String>,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok("wss://ws-feed.exchange.coinbase.com".into())
+    }
+
+    fn subscribe_messages(&self, symbols: &Vec<String>) -> Vec<String> {
+        let msg = json!({
+            "type": "subscribe",
+            "product_ids": symbols,
+            "channels": ["ticker"],
+        });
+        vec![msg.to_string()]
+    }
+
+    fn parse(&self, msg: &str) -> Vec<PairInfo> {
+        let mut result = Vec::<PairInfo>::new();
+        let msg: Value = match serde_json::from_str(msg) {
+            Ok(msg) => msg,
+            Err(_) => return result,
+        };
+        if msg["type"].as_str() != Some("ticker") {
+            return result;
+        }
+        if let (Some(symbol), Some(price), Some(volume), Some(time)) = (
+            msg["product_id"].as_str(),
+            stream::value_f64(&msg["price"]),
+            stream::value_f64(&msg["volume_24h"]),
+            msg["time"].as_str(),
+        ) {
+            if let Ok(time) = DateTime::parse_from_str(time, "%+") {
+                result.push(PairInfo {
+                    symbol: symbol.into(),
+                    price: price,
+                    volume: volume,
+                    timestamp: time.timestamp_millis() as u64,
+                    exchange: NAME.into(),
+                    depth: None,
+                    volume_unit: VolumeUnit::of_pair(&symbol),
+                    bid: stream::value_f64(&msg["best_bid"]),
+                    ask: stream::value_f64(&msg["best_ask"]),
+                });
+            }
+        }
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fetcher::expression;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
     #[tokio::test]
     async fn test_fetch() {
-        let coinbase = Coinbase::default();
-        let result = coinbase
-            .get_pairs(vec!["BTC-USD".into(), "BTC-USDT".into()])
-            .await;
-        let result = result.unwrap();
+        let url = mock::serve(vec![
+            mock::route(
+                "/products/BTC-USD/ticker",
+                StatusCode::OK,
+                include_str!("../../fixtures/coinbase/BTC-USD.json"),
+            ),
+            mock::route(
+                "/products/BTC-USDT/ticker",
+                StatusCode::OK,
+                include_str!("../../fixtures/coinbase/BTC-USDT.json"),
+            ),
+            mock::route(
+                "/products/USDT-USD/ticker",
+                StatusCode::OK,
+                include_str!("../../fixtures/coinbase/USDT-USD.json"),
+            ),
+        ])
+        .await;
+        let coinbase = Coinbase {
+            base_url: url,
+            ..Default::default()
+        };
+        let symbols =
+            expression::parse_symbols(&vec!["BTC-USD".into(), "BTC-USDT mul USDT-USD".into()])
+                .unwrap();
+        let legs = coinbase
+            .get_pairs(expression::expand_symbols(&symbols))
+            .await
+            .unwrap();
+        let (result, _) = expression::reduce_symbols(&symbols, &legs);
         println!("{:?}", result);
         assert_eq!(result.len(), 2);
+        assert_eq!(result[0].timestamp, 1660000000123);
+        // the composite pair takes the time of its oldest leg
+        assert_eq!(result[1].timestamp, 1659999998000);
+        // missing symbols are answered with 404
+        assert!(coinbase.get_pairs(vec!["XXX-USD".into()]).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let msg = r#"{"type":"ticker","product_id":"BTC-USD","price":"23000.10","volume_24h":"1234.5","time":"2022-08-08T12:00:00.123456Z"}"#;
+        let result = TickerStream {}.parse(msg);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].symbol, "BTC-USD");
+        assert_eq!(result[0].volume, 1234.5);
+        assert_eq!(result[0].timestamp, 1659960000123);
+        assert_eq!(TickerStream {}.parse(r#"{"type":"heartbeat"}"#).len(), 0);
     }
 }
@@ -0,0 +1,257 @@
+use super::Exchange;
+use super::{PairInfo, VolumeUnit};
+use crate::chains::dex::curve::{CurvePool, TokenExchangeFilter};
+use crate::chains::dex::{self, ChainReader};
+use async_trait::async_trait;
+use ethers::prelude::*;
+use std::error::Error;
+use std::str::FromStr;
+use strum_macros::Display;
+
+#[derive(Debug, Display)]
+pub enum CurveError {
+    InvalidSymbol(String),
+}
+impl std::error::Error for CurveError {}
+
+// one leg of curve, in the format of `${pool_address}:${i}:${j}`,
+// which means the price of coin `i` quoted by coin `j` in the pool
+#[derive(Debug, PartialEq)]
+pub struct PoolLeg {
+    pub pool: Address,
+    pub i: i128,
+    pub j: i128,
+}
+
+impl FromStr for PoolLeg {
+    type Err = CurveError;
+    fn from_str(symbol: &str) -> Result<Self, Self::Err> {
+        let invalid = || CurveError::InvalidSymbol(symbol.to_string());
+        let segments: Vec<&str> = symbol.split(":").collect();
+        if segments.len() != 3 {
+            return Err(invalid());
+        }
+        let pool = Address::from_str(segments[0]).map_err(|_| invalid())?;
+        let i = segments[1].parse::<i128>().map_err(|_| invalid())?;
+        let j = segments[2].parse::<i128>().map_err(|_| invalid())?;
+        if i < 0 || j < 0 || i == j {
+            return Err(invalid());
+        }
+        Ok(PoolLeg { pool, i, j })
+    }
+}
+
+#[derive(Clone)]
+pub struct Curve {
+    chain: ChainReader,
+}
+
+pub const NAME: &str = "curve";
+
+pub fn new(chain: ChainReader) -> Curve {
+    Curve { chain: chain }
+}
+
+#[async_trait]
+impl Exchange for Curve {
+    async fn get_pairs(
+        &self,
+        symbols: Vec<String>,
+    ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+        let provider = self.chain.provider()?;
+        let (last_number, timestamp) = self.chain.block_info(&provider).await?;
+        let mut result = Vec::<PairInfo>::new();
+        for symbol in symbols {
+            let leg = symbol.parse::<PoolLeg>()?;
+            let pool = CurvePool::new(leg.pool, provider.clone());
+            let coin_i = pool
+                .coins(U256::from(leg.i))
+                .block(last_number)
+                .call()
+                .await?;
+            let coin_j = pool
+                .coins(U256::from(leg.j))
+                .block(last_number)
+                .call()
+                .await?;
+            let decimals_i = dex::token_decimals(provider.clone(), coin_i).await?;
+            let decimals_j = dex::token_decimals(provider.clone(), coin_j).await?;
+            // how many coin j could be got by selling one coin i
+            let dy = pool
+                .get_dy(leg.i, leg.j, U256::exp10(decimals_i as usize))
+                .block(last_number)
+                .call()
+                .await?;
+            // volume of coin i traded in the last 24 hours
+            let exchanges = dex::query_logs(
+                pool.event::<TokenExchangeFilter>(),
+                last_number.saturating_sub(dex::BLOCKS_PER_DAY),
+                last_number,
+            )
+            .await
+            .and_then(|exchanges| {
+                let mut volume = U256::zero();
+                for exchange in exchanges {
+                    if exchange.sold_id == leg.i {
+                        volume += exchange.tokens_sold;
+                    }
+                    if exchange.bought_id == leg.i {
+                        volume += exchange.tokens_bought;
+                    }
+                }
+                dex::to_float(volume, decimals_i)
+            });
+            let volume = dex::keep_volume(&self.chain.volumes, &symbol, exchanges)?;
+            result.push(PairInfo {
+                symbol: symbol.clone(),
+                price: dex::to_float(dy, decimals_j)?,
+                volume: volume,
+                timestamp: timestamp,
+                exchange: NAME.into(),
+                depth: None,
+                volume_unit: VolumeUnit::base(None),
+                bid: None,
+                ask: None,
+            });
+        }
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
+    #[test]
+    fn test_parse_leg() {
+        let leg = "0xbebc44782c7db0a1a60cb6fe97d0b483032ff1c7:1:2"
+            .parse::<PoolLeg>()
+            .unwrap();
+        assert_eq!(leg.i, 1);
+        assert_eq!(leg.j, 2);
+        assert!("0xbebc44782c7db0a1a60cb6fe97d0b483032ff1c7:1"
+            .parse::<PoolLeg>()
+            .is_err());
+        assert!("0xbebc44782c7db0a1a60cb6fe97d0b483032ff1c7:1:1"
+            .parse::<PoolLeg>()
+            .is_err());
+        assert!("3pool:0:1".parse::<PoolLeg>().is_err());
+    }
+
+    // a JSON-RPC answering a pool of 3 coins at block 20000, the volume of coin 1 is 2 in the first page
+    // of logs and 3 in the third one, while a failing page answers an error instead of its logs
+    async fn serve_rpc(failing_page: Option<&str>) -> String {
+        let word = |n: u64| format!("{:064x}", n);
+        let result = |result: String| format!(r#"{{"jsonrpc":"2.0","id":1,"result":{}}}"#, result);
+        let exchange = |sold_id: u64, sold: u64, bought_id: u64, bought: u64| {
+            format!(
+                r#"[{{"address":"0xbebc44782c7db0a1a60cb6fe97d0b483032ff1c7","topics":["{:?}","0x{}"],"data":"0x{}{}{}{}"}}]"#,
+                TokenExchangeFilter::signature(),
+                word(0),
+                word(sold_id),
+                word(sold),
+                word(bought_id),
+                word(bought)
+            )
+        };
+        let pages = vec![
+            ("0x3200", exchange(1, 2000000, 2, 1999000)),
+            ("0x39d0", "[]".to_string()),
+            ("0x41a0", exchange(0, 3000000, 1, 3000000)),
+            ("0x4970", "[]".to_string()),
+        ];
+        let mut routes = vec![
+            mock::route(
+                "eth_getBlockByNumber",
+                StatusCode::OK,
+                &result(r#"{"number":"0x4e20","timestamp":"0x62bd0c1b"}"#.into()),
+            ),
+            mock::route(
+                &format!("0xc6610657{}", word(1)),
+                StatusCode::OK,
+                &result(format!(
+                    r#""0x{:0>64}""#,
+                    "a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"
+                )),
+            ),
+            mock::route(
+                &format!("0xc6610657{}", word(2)),
+                StatusCode::OK,
+                &result(format!(
+                    r#""0x{:0>64}""#,
+                    "dac17f958d2ee523a2206206994597c13d831ec7"
+                )),
+            ),
+            mock::route(
+                "0x313ce567",
+                StatusCode::OK,
+                &result(format!(r#""0x{}""#, word(6))),
+            ),
+            mock::route(
+                "0x5e0d443f",
+                StatusCode::OK,
+                &result(format!(r#""0x{}""#, word(999500))),
+            ),
+        ];
+        for (from, logs) in pages {
+            let pattern = format!(r#""fromBlock":"{}""#, from);
+            match failing_page {
+                Some(page) if page == from => {
+                    routes.push(mock::route(&pattern, StatusCode::INTERNAL_SERVER_ERROR, ""))
+                }
+                _ => routes.push(mock::route(&pattern, StatusCode::OK, &result(logs))),
+            }
+        }
+        mock::serve(routes).await
+    }
+
+    #[tokio::test]
+    async fn test_fetch_pages() {
+        let symbols = vec!["0xbebc44782c7db0a1a60cb6fe97d0b483032ff1c7:1:2".to_string()];
+        let chain = ChainReader {
+            rpc_url: serve_rpc(None).await,
+            block: Some(20000),
+            volumes: Default::default(),
+        };
+        let result = new(chain.clone()).get_pairs(symbols.clone()).await.unwrap();
+        assert_eq!(result[0].price, 0.9995);
+        assert_eq!(result[0].volume, 5.0);
+        assert_eq!(result[0].timestamp, 0x62bd0c1b * 1000);
+        // a failing page keeps the last volume of the pool, which fails only if it has none yet
+        let failing = ChainReader {
+            rpc_url: serve_rpc(Some("0x39d0")).await,
+            ..chain.clone()
+        };
+        let result = new(failing.clone())
+            .get_pairs(symbols.clone())
+            .await
+            .unwrap();
+        assert_eq!(result[0].volume, 5.0);
+        let fresh = ChainReader {
+            volumes: Default::default(),
+            ..failing
+        };
+        assert!(new(fresh).get_pairs(symbols).await.is_err());
+    }
+
+    // reads mainnet through a public RPC, run with `cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_fetch() {
+        let curve = new(ChainReader {
+            rpc_url: "https://cloudflare-eth.com".into(),
+            block: None,
+            volumes: Default::default(),
+        });
+        let result = curve
+            .get_pairs(vec![
+                "0xbebc44782c7db0a1a60cb6fe97d0b483032ff1c7:1:2".into(), //USDC-USDT of 3pool
+                "0xdc24316b9ae028f1497c275eb9192a3ea0f67022:1:0".into(), //stETH-ETH
+            ])
+            .await;
+        let result = result.unwrap();
+        println!("{:?}", result);
+        assert_eq!(result.len(), 2);
+    }
+}
@@ -1,133 +1,497 @@
-use crate::fetcher::PairInfo;
+use crate::fetcher::{self, PairInfo, Side, VolumeUnit};
 use std::collections::BTreeMap;
+use std::fmt;
+use strum_macros::Display;
 
+// Symbol is one configured mapping, which is a plain symbol of the exchange, or an expression like
+// `(A mul B) div C`, `inv X`, `X mul 1e12` and `avg(X, Y)`, parsed once when loaded
+#[derive(Debug, Clone, PartialEq)]
 pub struct Symbol {
     pub symbol: String,
+    pub expr: Expr,
 }
 
-pub fn expand_symbols(origin_symbols: &Vec<String>) -> Vec<String> {
-    let mut symbols = Vec::<String>::new();
-    for sym_s in origin_symbols {
-        let sym = Symbol {
-            symbol: sym_s.clone(),
+#[derive(Debug, Display, Clone, PartialEq)]
+pub enum ExprError {
+    MissingLeg(String),
+    DivisionByZero(String),
+    UnknownOperator(String),
+    NonFinite(String),
+    Syntax(String),
+}
+impl std::error::Error for ExprError {}
+
+// Expr is the syntax tree of an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Leg(String),
+    Const(f64),
+    Inv(Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Avg(Vec<Expr>), //mean of alternatives available
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Comma,
+    Word(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+            Token::Word(word) => write!(f, "{}", word),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Vec<(usize, Token)> {
+    let mut tokens = Vec::<(usize, Token)>::new();
+    let mut word_start: Option<usize> = None;
+    for (i, c) in text.char_indices() {
+        let token = match c {
+            '(' => Some(Token::LParen),
+            ')' => Some(Token::RParen),
+            ',' => Some(Token::Comma),
+            _ if c.is_whitespace() => None,
+            _ => {
+                if word_start.is_none() {
+                    word_start = Some(i);
+                }
+                continue;
+            }
         };
-        if sym.is_expression() {
-            for seg in sym.symbol.split(" ") {
-                if seg.eq("div") || seg.eq("mul") {
-                    continue;
+        if let Some(start) = word_start.take() {
+            tokens.push((start, Token::Word(text[start..i].into())));
+        }
+        if let Some(token) = token {
+            tokens.push((i, token));
+        }
+    }
+    if let Some(start) = word_start {
+        tokens.push((start, Token::Word(text[start..].into())));
+    }
+    tokens
+}
+
+// recursive descent parser, `mul` and `div` have the same precedence and are left associative
+//   expr := term (("mul" | "div") term)*
+//   term := "inv" term | "(" expr ")" | "avg" "(" expr ("," expr)* ")" | number | symbol
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn unexpected(&self, expected: &str) -> ExprError {
+        match self.tokens.get(self.pos) {
+            Some((offset, token)) => ExprError::Syntax(format!(
+                "expected {} but found `{}` at {}",
+                expected, token, offset
+            )),
+            None => ExprError::Syntax(format!("expected {} but found the end", expected)),
+        }
+    }
+
+    fn expect(&mut self, token: Token, expected: &str) -> Result<(), ExprError> {
+        if self.peek() != Some(&token) {
+            return Err(self.unexpected(expected));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<Expr, ExprError> {
+        let mut acc = self.term()?;
+        loop {
+            match self.peek() {
+                Some(Token::Word(op)) if op == "mul" => {
+                    self.pos += 1;
+                    acc = Expr::Mul(Box::new(acc), Box::new(self.term()?));
+                }
+                Some(Token::Word(op)) if op == "div" => {
+                    self.pos += 1;
+                    acc = Expr::Div(Box::new(acc), Box::new(self.term()?));
+                }
+                Some(Token::Word(op)) => return Err(ExprError::UnknownOperator(op.clone())),
+                _ => return Ok(acc),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, ExprError> {
+        match self.peek().cloned() {
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let inner = self.expr()?;
+                self.expect(Token::RParen, "`)`")?;
+                Ok(inner)
+            }
+            Some(Token::Word(word)) if word == "inv" => {
+                self.pos += 1;
+                Ok(Expr::Inv(Box::new(self.term()?)))
+            }
+            Some(Token::Word(word)) if word == "avg" => {
+                self.pos += 1;
+                self.expect(Token::LParen, "`(` after avg")?;
+                let mut alternatives = vec![self.expr()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                    alternatives.push(self.expr()?);
+                }
+                self.expect(Token::RParen, "`)` or `,`")?;
+                Ok(Expr::Avg(alternatives))
+            }
+            Some(Token::Word(word)) if word == "mul" || word == "div" => {
+                Err(self.unexpected("a symbol or a number"))
+            }
+            Some(Token::Word(word)) => {
+                let offset = self.tokens[self.pos].0;
+                self.pos += 1;
+                number(&word, offset).unwrap_or(Ok(Expr::Leg(word)))
+            }
+            _ => Err(self.unexpected("a symbol or a number")),
+        }
+    }
+}
+
+// number literals are finite decimals, optionally in scientific notation like `1e12` or `2.5E-3`,
+// other words parsed as floats like `inf`, `NaN` or `-1` are refused rather than taken as symbols
+fn number(word: &str, offset: usize) -> Option<Result<Expr, ExprError>> {
+    let plain = word.chars().all(|c| c.is_ascii_digit() || c == '.');
+    let numeric = word.starts_with(|c: char| c.is_ascii_digit() || c == '.');
+    match word.parse::<f64>() {
+        Ok(value) if numeric && value.is_finite() => Some(Ok(Expr::Const(value))),
+        Ok(_) => Some(Err(ExprError::Syntax(format!(
+            "expected a finite number but found `{}` at {}",
+            word, offset
+        )))),
+        Err(_) if plain => Some(Err(ExprError::Syntax(format!(
+            "invalid number `{}` at {}",
+            word, offset
+        )))),
+        Err(_) => None,
+    }
+}
+
+// parse the text of a mapping into its syntax tree, it should refer to one symbol at least
+pub fn parse(text: &str) -> Result<Expr, ExprError> {
+    let mut parser = Parser {
+        tokens: tokenize(text),
+        pos: 0,
+    };
+    let expr = parser.expr()?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected("an operator"));
+    }
+    let legs = expr.legs();
+    if legs.is_empty() {
+        return Err(ExprError::Syntax("no symbol referred".into()));
+    }
+    for leg in legs.iter().filter(|leg| leg.starts_with('@')) {
+        match cross_leg(leg) {
+            Some((exchange, symbol)) if exchange.len() > 0 && symbol.len() > 0 => {}
+            _ => {
+                return Err(ExprError::Syntax(format!(
+                    "expected `@exchange:symbol` but found `{}`",
+                    leg
+                )))
+            }
+        }
+    }
+    Ok(expr)
+}
+
+pub fn parse_symbol(text: &str) -> Result<Symbol, ExprError> {
+    Ok(Symbol {
+        symbol: text.into(),
+        expr: parse(text)?,
+    })
+}
+
+pub fn parse_symbols(texts: &Vec<String>) -> Result<Vec<Symbol>, ExprError> {
+    texts.iter().map(|text| parse_symbol(text)).collect()
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Leg(symbol) => write!(f, "{}", symbol),
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Inv(inner) => write!(f, "inv {}", inner),
+            Expr::Mul(a, b) => write!(f, "({} mul {})", a, b),
+            Expr::Div(a, b) => write!(f, "({} div {})", a, b),
+            Expr::Avg(alternatives) => {
+                let items: Vec<String> = alternatives.iter().map(|e| e.to_string()).collect();
+                write!(f, "avg({})", items.join(", "))
+            }
+        }
+    }
+}
+
+impl Expr {
+    // symbols referred by the expression, in order of appearance
+    pub fn legs(&self) -> Vec<String> {
+        let mut legs = Vec::<String>::new();
+        self.collect_legs(&mut legs);
+        legs
+    }
+
+    fn collect_legs(&self, legs: &mut Vec<String>) {
+        match self {
+            Expr::Leg(symbol) => {
+                if !legs.contains(symbol) {
+                    legs.push(symbol.clone());
+                }
+            }
+            Expr::Const(_) => {}
+            Expr::Inv(inner) => inner.collect_legs(legs),
+            Expr::Mul(a, b) | Expr::Div(a, b) => {
+                a.collect_legs(legs);
+                b.collect_legs(legs);
+            }
+            Expr::Avg(alternatives) => {
+                for alternative in alternatives {
+                    alternative.collect_legs(legs);
                 }
-                symbols.push(seg.into());
             }
-        } else {
-            symbols.push(sym_s.clone());
+        }
+    }
+
+    // inverted tells whether the price of the leg is inverted in the expression, by `inv` or as a divisor
+    pub fn inverted(&self, leg: &str) -> Option<bool> {
+        match self {
+            Expr::Leg(symbol) => (symbol == leg).then(|| false),
+            Expr::Const(_) => None,
+            Expr::Inv(inner) => inner.inverted(leg).map(|inverted| !inverted),
+            Expr::Mul(a, b) => a.inverted(leg).or_else(|| b.inverted(leg)),
+            Expr::Div(a, b) => a
+                .inverted(leg)
+                .or_else(|| b.inverted(leg).map(|inverted| !inverted)),
+            Expr::Avg(alternatives) => alternatives.iter().find_map(|a| a.inverted(leg)),
+        }
+    }
+
+    pub fn eval(&self, value_table: &BTreeMap<&str, f64>) -> Result<f64, ExprError> {
+        match self {
+            Expr::Leg(symbol) => value_table
+                .get(symbol.as_str())
+                .copied()
+                .ok_or(ExprError::MissingLeg(symbol.clone())),
+            Expr::Const(value) => Ok(*value),
+            Expr::Inv(inner) => {
+                let value = inner.eval(value_table)?;
+                if value == 0.0 {
+                    return Err(ExprError::DivisionByZero(inner.to_string()));
+                }
+                Ok(1.0 / value)
+            }
+            Expr::Mul(a, b) => Ok(a.eval(value_table)? * b.eval(value_table)?),
+            Expr::Div(a, b) => {
+                let numerator = a.eval(value_table)?;
+                let denominator = b.eval(value_table)?;
+                if denominator == 0.0 {
+                    return Err(ExprError::DivisionByZero(b.to_string()));
+                }
+                Ok(numerator / denominator)
+            }
+            Expr::Avg(alternatives) => {
+                // alternatives which can not be evaluated are ignored, unless all of them fail
+                let mut values = Vec::<f64>::new();
+                let mut first_err = None;
+                for alternative in alternatives {
+                    match alternative.eval(value_table) {
+                        Ok(value) => values.push(value),
+                        Err(err) => {
+                            first_err.get_or_insert(err);
+                        }
+                    }
+                }
+                if values.is_empty() {
+                    return Err(first_err.unwrap());
+                }
+                Ok(values.iter().sum::<f64>() / values.len() as f64)
+            }
+        }
+    }
+}
+
+// a leg like `@coinbase:USDT-USD` refers to a symbol of another exchange
+pub fn cross_leg(leg: &str) -> Option<(&str, &str)> {
+    leg.strip_prefix('@')?.split_once(':')
+}
+
+// legs to fetch from each exchange, cross-exchange legs are fetched from the exchange referred
+pub fn legs_by_exchange(mappings: &BTreeMap<String, Vec<Symbol>>) -> BTreeMap<String, Vec<String>> {
+    let mut result = BTreeMap::<String, Vec<String>>::new();
+    for (exchange, symbols) in mappings {
+        for leg in expand_symbols(symbols) {
+            let (exchange, symbol) = match cross_leg(&leg) {
+                Some((exchange, symbol)) => (exchange.to_string(), symbol.to_string()),
+                None => (exchange.clone(), leg),
+            };
+            let legs = result.entry(exchange).or_insert(Vec::new());
+            if !legs.contains(&symbol) {
+                legs.push(symbol);
+            }
+        }
+    }
+    result
+}
+
+pub fn expand_symbols(origin_symbols: &Vec<Symbol>) -> Vec<String> {
+    let mut symbols = Vec::<String>::new();
+    for sym_s in origin_symbols {
+        for leg in sym_s.expr.legs() {
+            if !symbols.contains(&leg) {
+                symbols.push(leg);
+            }
         }
     }
     return symbols;
 }
 
-pub fn reduce_symbols(symbols: &Vec<String>, crawl_result: &Vec<PairInfo>) -> Vec<PairInfo> {
+// evaluate configured symbols by quotes of their legs, expressions failed are returned with the reason
+pub fn reduce_symbols(
+    symbols: &Vec<Symbol>,
+    crawl_result: &Vec<PairInfo>,
+) -> (Vec<PairInfo>, Vec<(String, ExprError)>) {
     let mut result = Vec::<PairInfo>::new();
+    let mut skipped = Vec::<(String, ExprError)>::new();
     for symbol in symbols {
-        let symb = Symbol {
-            symbol: symbol.clone(),
-        };
-        let pair_ex = symb.eval_pair(crawl_result);
-        if !pair_ex.is_none() {
-            result.push(pair_ex.unwrap());
+        match symbol.eval_pair(crawl_result) {
+            Ok(pair) => result.push(pair),
+            Err(err) => skipped.push((symbol.symbol.clone(), err)),
         }
     }
-    return result;
+    return (result, skipped);
 }
 
 impl Symbol {
     pub fn is_expression(&self) -> bool {
-        self.symbol.contains(" ")
+        !matches!(self.expr, Expr::Leg(_))
     }
 
-    pub fn eval_pair(&self, ref_pairs: &Vec<PairInfo>) -> Option<PairInfo> {
-        let price = self.eval_price(ref_pairs);
-        if price.is_none() {
-            return None;
+    pub fn eval_pair(&self, ref_pairs: &Vec<PairInfo>) -> Result<PairInfo, ExprError> {
+        let expr = &self.expr;
+        let price = self.eval_price(ref_pairs)?;
+        let legs = expr.legs();
+        // volume is taken from the first leg available
+        let base_pair = legs
+            .iter()
+            .find_map(|leg| ref_pairs.iter().find(|pair| pair.symbol.eq(leg)))
+            .ok_or(ExprError::MissingLeg(legs[0].clone()))?;
+        let mut new_pair = base_pair.clone();
+        new_pair.symbol = self.symbol.clone();
+        new_pair.price = price;
+        // volume is counted in the base asset of the expression, which is the quote asset of an inverted leg
+        let volume = fetcher::base_volume(base_pair);
+        new_pair.volume_unit = VolumeUnit::base(None);
+        if expr.inverted(&base_pair.symbol) == Some(true) {
+            new_pair.volume = volume * base_pair.price;
+        } else {
+            new_pair.volume = volume;
+            if base_pair.volume_unit.side == Side::Base {
+                new_pair.volume_unit.asset = base_pair.volume_unit.asset.clone();
+            }
         }
-        let mut base_symbol: String = "".to_string();
-        for seg in self.symbol.split(" ") {
-            base_symbol = seg.into(); //first one
-            break;
+        // a composite quote is as wide as all of its legs, by their relative spreads summed up
+        if !matches!(expr, Expr::Leg(_)) {
+            let spreads: Option<Vec<f64>> = legs
+                .iter()
+                .map(|leg| {
+                    let pair = ref_pairs.iter().find(|pair| pair.symbol.eq(leg))?;
+                    fetcher::spread(pair)
+                })
+                .collect();
+            let spread: Option<f64> = spreads.map(|spreads| spreads.iter().sum());
+            new_pair.bid = spread.map(|spread| price * (1.0 - spread / 2.0));
+            new_pair.ask = spread.map(|spread| price * (1.0 + spread / 2.0));
         }
-        for pair in ref_pairs {
-            if pair.symbol.eq(&base_symbol) {
-                let mut new_pair = pair.clone();
-                new_pair.symbol = self.symbol.clone();
-                new_pair.price = price.unwrap();
-                return Some(new_pair);
+        // a composite price is only as fresh as its oldest leg
+        for leg in &legs {
+            for pair in ref_pairs.iter().filter(|p| p.symbol.eq(leg)) {
+                new_pair.timestamp = new_pair.timestamp.min(pair.timestamp);
             }
         }
-        return None;
+        Ok(new_pair)
     }
 
-    pub fn eval_price(&self, ref_pairs: &Vec<PairInfo>) -> Option<f64> {
-        let segments = self.symbol.split(" ");
-        let mut value_table = BTreeMap::<String, f64>::new();
-        let mut cur_op = "";
-        let mut acc: f64 = 1.0;
+    pub fn eval_price(&self, ref_pairs: &Vec<PairInfo>) -> Result<f64, ExprError> {
+        let mut value_table = BTreeMap::<&str, f64>::new();
         for pair in ref_pairs {
-            value_table.insert(pair.symbol.clone(), pair.price);
+            value_table.insert(pair.symbol.as_str(), pair.price);
         }
-        for seg in segments {
-            if seg.len() == 0 || seg.eq(" ") {
-                continue;
-            }
-            if seg == "div" || seg == "mul" {
-                cur_op = seg;
-                continue;
-            }
-            if cur_op.eq("") || cur_op.eq("mul") {
-                acc = acc
-                    * value_table
-                        .get(seg)
-                        .expect(format!("{} not found", seg).as_str());
-            } else if cur_op.eq("div") {
-                acc = acc
-                    / value_table
-                        .get(seg)
-                        .expect(format!("{} not found", seg).as_str());
-            } else {
-                return None;
-            }
+        let acc = self.expr.eval(&value_table)?;
+        if !acc.is_finite() {
+            return Err(ExprError::NonFinite(self.symbol.clone()));
         }
-        return Some(acc);
+        return Ok(acc);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::fetcher::PairInfo;
     #[test]
     fn test_basic_expression() {
-        let x = Symbol {
-            symbol: "a div b".into(),
-        };
-        let y = Symbol {
-            symbol: "a mul b".into(),
-        };
-        let z = Symbol {
-            symbol: "a mul b div a".into(),
-        };
+        let x = parse_symbol("a div b").unwrap();
+        let y = parse_symbol("a mul b").unwrap();
+        let z = parse_symbol("a mul b div a").unwrap();
         let mut ref_pairs = Vec::<PairInfo>::new();
         ref_pairs.push(PairInfo {
             symbol: "a".into(),
             price: 8.0,
             volume: 0.0,
-            timestamp: 0,
+            timestamp: 2000,
             exchange: "tiex".into(),
+            depth: None,
+            volume_unit: VolumeUnit::base(None),
+            bid: None,
+            ask: None,
         });
         ref_pairs.push(PairInfo {
             symbol: "b".into(),
             price: 2.0,
             volume: 0.0,
-            timestamp: 0,
+            timestamp: 1000,
+            exchange: "tiex".into(),
+            depth: None,
+            volume_unit: VolumeUnit::base(None),
+            bid: None,
+            ask: None,
+        });
+        ref_pairs.push(PairInfo {
+            symbol: "zero".into(),
+            price: 0.0,
+            volume: 0.0,
+            timestamp: 1000,
             exchange: "tiex".into(),
+            depth: None,
+            volume_unit: VolumeUnit::base(None),
+            bid: None,
+            ask: None,
+        });
+        ref_pairs.push(PairInfo {
+            symbol: "huge".into(),
+            price: f64::MAX,
+            volume: 0.0,
+            timestamp: 1000,
+            exchange: "tiex".into(),
+            depth: None,
+            volume_unit: VolumeUnit::base(None),
+            bid: None,
+            ask: None,
         });
         assert_eq!(x.is_expression(), true);
         let result = x.eval_price(&ref_pairs);
@@ -135,6 +499,202 @@ mod tests {
         let result = y.eval_price(&ref_pairs);
         assert_eq!(result.unwrap(), 16.0);
         println!("{:?}", x.eval_pair(&ref_pairs));
+        assert_eq!(x.eval_pair(&ref_pairs).unwrap().timestamp, 1000);
         assert_eq!(z.eval_price(&ref_pairs).unwrap(), 2.0);
+        let err = |symbol: &str| {
+            parse_symbol(symbol)
+                .and_then(|symbol| symbol.eval_price(&ref_pairs))
+                .unwrap_err()
+        };
+        assert_eq!(err("a div c"), ExprError::MissingLeg("c".into()));
+        assert_eq!(err("a div zero"), ExprError::DivisionByZero("zero".into()));
+        assert_eq!(err("a plus b"), ExprError::UnknownOperator("plus".into()));
+        assert_eq!(
+            err("huge mul huge"),
+            ExprError::NonFinite("huge mul huge".into())
+        );
+        let (result, skipped) = reduce_symbols(
+            &parse_symbols(&vec!["a div b".into(), "a div c".into()]).unwrap(),
+            &ref_pairs,
+        );
+        assert_eq!(result.len(), 1);
+        assert_eq!(skipped[0].0, "a div c");
+    }
+
+    #[test]
+    fn test_parse() {
+        let leg = |symbol: &str| Box::new(Expr::Leg(symbol.into()));
+        assert_eq!(
+            parse("(A mul B) div C").unwrap(),
+            Expr::Div(Box::new(Expr::Mul(leg("A"), leg("B"))), leg("C"))
+        );
+        assert_eq!(
+            parse("inv X mul 1e12").unwrap(),
+            Expr::Mul(Box::new(Expr::Inv(leg("X"))), Box::new(Expr::Const(1e12)))
+        );
+        assert_eq!(
+            parse("avg(BTC/USD, BTC/USDT div USDT/USD)").unwrap(),
+            Expr::Avg(vec![
+                Expr::Leg("BTC/USD".into()),
+                Expr::Div(leg("BTC/USDT"), leg("USDT/USD")),
+            ])
+        );
+        assert_eq!(parse("1INCH-USDT").unwrap(), Expr::Leg("1INCH-USDT".into()));
+        assert_eq!(
+            parse("(A mul B div C").unwrap_err(),
+            ExprError::Syntax("expected `)` but found the end".into())
+        );
+        assert_eq!(
+            parse("A mul div B").unwrap_err(),
+            ExprError::Syntax("expected a symbol or a number but found `div` at 6".into())
+        );
+        assert_eq!(
+            parse("A mul 1000)").unwrap_err(),
+            ExprError::Syntax("expected an operator but found `)` at 10".into())
+        );
+        assert!(parse("mul 2").is_err());
+        assert!(parse("1000").is_err());
+        // scientific notation is taken, infinite numbers are not
+        assert_eq!(
+            parse("X mul 1e12").unwrap(),
+            Expr::Mul(leg("X"), Box::new(Expr::Const(1e12)))
+        );
+        assert_eq!(
+            parse("X mul 1E-6").unwrap(),
+            Expr::Mul(leg("X"), Box::new(Expr::Const(1e-6)))
+        );
+        assert_eq!(
+            parse("X mul 2.5e+3").unwrap(),
+            Expr::Mul(leg("X"), Box::new(Expr::Const(2500.0)))
+        );
+        assert_eq!(
+            parse("X mul inf").unwrap_err(),
+            ExprError::Syntax("expected a finite number but found `inf` at 6".into())
+        );
+        assert!(parse("X mul 1e400").is_err());
+        assert!(parse("X div NaN").is_err());
+        assert!(parse(&format!("X mul 1{}", "0".repeat(400))).is_err());
+        assert!(parse("X mul 1.2.3").is_err());
+        assert_eq!(
+            expand_symbols(
+                &parse_symbols(&vec!["avg(A, B div C)".into(), "inv A".into()]).unwrap()
+            ),
+            vec!["A", "B", "C"]
+        );
+        let mut ref_pairs = Vec::<PairInfo>::new();
+        for (symbol, price) in [("A", 2.0), ("B", 8.0)] {
+            ref_pairs.push(PairInfo {
+                symbol: symbol.into(),
+                price: price,
+                volume: 1.0,
+                timestamp: 0,
+                exchange: "tiex".into(),
+                depth: None,
+                volume_unit: VolumeUnit::base(None),
+                bid: None,
+                ask: None,
+            });
+        }
+        let price =
+            |symbol: &str| parse_symbol(symbol).and_then(|symbol| symbol.eval_price(&ref_pairs));
+        assert_eq!(price("inv A mul 100").unwrap(), 50.0);
+        assert_eq!(price("B div (A mul A)").unwrap(), 2.0);
+        // missing alternatives are ignored
+        assert_eq!(price("avg(A, B, C)").unwrap(), 5.0);
+        assert_eq!(
+            price("avg(C, D)").unwrap_err(),
+            ExprError::MissingLeg("C".into())
+        );
+    }
+
+    #[test]
+    fn test_volume_units() {
+        let leg = |symbol: &str, price: f64, volume: f64, volume_unit: VolumeUnit| PairInfo {
+            symbol: symbol.into(),
+            price: price,
+            volume: volume,
+            timestamp: 0,
+            exchange: "tiex".into(),
+            depth: None,
+            volume_unit: volume_unit,
+            bid: None,
+            ask: None,
+        };
+        let ref_pairs = vec![
+            leg("WETH-USDC", 2000.0, 3.0, VolumeUnit::base(Some("WETH"))),
+            leg("BTC-USD", 20000.0, 40000.0, VolumeUnit::quote(None)),
+        ];
+        let pair = |symbol: &str| {
+            parse_symbol(symbol)
+                .and_then(|symbol| symbol.eval_pair(&ref_pairs))
+                .unwrap()
+        };
+        let plain = pair("WETH-USDC");
+        assert_eq!(plain.volume, 3.0);
+        assert_eq!(plain.volume_unit, VolumeUnit::base(Some("WETH")));
+        // the base asset of an inverted leg is its quote asset
+        let inverted = pair("inv WETH-USDC");
+        assert_eq!(inverted.volume, 6000.0);
+        assert_eq!(inverted.volume_unit, VolumeUnit::base(None));
+        assert_eq!(pair("1 div WETH-USDC").volume, 6000.0);
+        // quote volumes are converted to the base asset
+        assert_eq!(pair("BTC-USD mul 1").volume, 2.0);
+        assert_eq!(pair("BTC-USD").volume_unit.side, Side::Base);
+    }
+
+    #[test]
+    fn test_composite_spread() {
+        let mut ref_pairs = Vec::<PairInfo>::new();
+        for (symbol, price, spread) in [("A", 100.0, 0.02), ("B", 2.0, 0.01)] {
+            ref_pairs.push(PairInfo {
+                symbol: symbol.into(),
+                price: price,
+                volume: 1.0,
+                timestamp: 0,
+                exchange: "tiex".into(),
+                depth: None,
+                volume_unit: VolumeUnit::base(None),
+                bid: Some(price * (1.0 - spread / 2.0)),
+                ask: Some(price * (1.0 + spread / 2.0)),
+            });
+        }
+        let pair = |symbol: &str| {
+            parse_symbol(symbol)
+                .and_then(|symbol| symbol.eval_pair(&ref_pairs))
+                .unwrap()
+        };
+        assert_eq!(pair("A").bid, Some(99.0));
+        let composite = pair("A div B");
+        assert!((fetcher::spread(&composite).unwrap() - 0.03).abs() < 1e-9);
+        // spread is unknown if any leg is missing
+        assert_eq!(pair("avg(A, C)").bid, None);
+    }
+
+    #[test]
+    fn test_cross_legs() {
+        assert_eq!(
+            cross_leg("@coinbase:USDT-USD"),
+            Some(("coinbase", "USDT-USD"))
+        );
+        assert_eq!(cross_leg("USDT-USD"), None);
+        assert!(parse("BTC-USDT mul @coinbase").is_err());
+        assert!(parse("BTC-USDT mul @:USDT-USD").is_err());
+        let mut mappings = BTreeMap::<String, Vec<Symbol>>::new();
+        mappings.insert(
+            "kucoin".into(),
+            parse_symbols(&vec![
+                "BTC-USDT mul @coinbase:USDT-USD".into(),
+                "BTC-USDC".into(),
+            ])
+            .unwrap(),
+        );
+        mappings.insert(
+            "okex".into(),
+            parse_symbols(&vec!["BTC-USDT div @okex:USDC-USDT".into()]).unwrap(),
+        );
+        let legs = legs_by_exchange(&mappings);
+        assert_eq!(legs["kucoin"], vec!["BTC-USDT", "BTC-USDC"]);
+        assert_eq!(legs["coinbase"], vec!["USDT-USD"]);
+        assert_eq!(legs["okex"], vec!["BTC-USDT", "USDC-USDT"]);
     }
 }
@@ -0,0 +1,95 @@
+use super::http::HttpClient;
+use super::Exchange;
+use crate::fetcher::{PairInfo, VolumeUnit};
+use async_trait::async_trait;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::error::Error;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pair {
+    pub currency_pair: String,
+    pub last: String,
+    pub lowest_ask: String,
+    pub highest_bid: String,
+    pub base_volume: String,  //in base currency
+    pub quote_volume: String, //in quote currency
+}
+
+pub struct Gateio {
+    pub base_url: String,
+    pub http: HttpClient,
+}
+
+impl Default for Gateio {
+    fn default() -> Self {
+        Gateio {
+            base_url: BASE_URL.into(),
+            http: HttpClient::default(),
+        }
+    }
+}
+
+pub const NAME: &str = "gateio";
+pub const BASE_URL: &str = "https://api.gateio.ws";
+
+#[async_trait]
+impl Exchange for Gateio {
+    async fn get_pairs(
+        &self,
+        symbols: Vec<String>,
+    ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+        let request_url = format!("{}/api/v4/spot/tickers", self.base_url);
+        let response = self.http.send(self.http.get(&request_url)).await?;
+        let timestamp = super::server_time(&response).ok_or("no Date header in response")?;
+        let pair_list: Vec<Pair> = response.json().await?;
+        let mut result = Vec::<PairInfo>::new();
+        for pair in &pair_list {
+            if symbols.contains(&pair.currency_pair) {
+                result.push(PairInfo {
+                    symbol: pair.currency_pair.clone(),
+                    price: pair.last.parse::<f64>()?,
+                    volume: pair.base_volume.parse::<f64>()?,
+                    timestamp: timestamp,
+                    exchange: NAME.into(),
+                    depth: None,
+                    volume_unit: VolumeUnit::of_pair(&pair.currency_pair),
+                    bid: pair.highest_bid.parse::<f64>().ok(),
+                    ask: pair.lowest_ask.parse::<f64>().ok(),
+                });
+            }
+        }
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::expression;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
+    #[tokio::test]
+    async fn test_fetch() {
+        let url = mock::serve(vec![mock::route(
+            "/api/v4/spot/tickers",
+            StatusCode::OK,
+            include_str!("../../fixtures/gateio/tickers.json"),
+        )])
+        .await;
+        let gateio = Gateio {
+            base_url: url,
+            ..Default::default()
+        };
+        let symbols = expression::parse_symbols(&vec!["BTC_USDT".into()]).unwrap();
+        let legs = gateio
+            .get_pairs(expression::expand_symbols(&symbols))
+            .await
+            .unwrap();
+        let (result, _) = expression::reduce_symbols(&symbols, &legs);
+        println!("{:?}", result);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].price, 23010.5);
+        assert_eq!(result[0].volume, 2100.123456);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::flags::HttpConfig;
+use reqwest::{Client, ClientBuilder, Proxy, RequestBuilder, Response};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+// HttpClient is shared by all fetchers, so connections are pooled and kept alive between rounds
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    max_per_host: usize,
+    hosts: Arc<Mutex<BTreeMap<String, Arc<Semaphore>>>>, //host -> permits of in-flight requests
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        new(&HttpConfig::default()).expect("unable to build http client")
+    }
+}
+
+pub fn new(cfg: &HttpConfig) -> Result<HttpClient, Box<dyn Error + Send + Sync>> {
+    let mut builder = ClientBuilder::new()
+        .timeout(Duration::from_secs(cfg.timeout))
+        .connect_timeout(Duration::from_secs(cfg.connect_timeout))
+        .user_agent(cfg.user_agent.clone())
+        .gzip(cfg.gzip);
+    if let Some(proxy) = &cfg.proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+    Ok(HttpClient {
+        client: builder.build()?,
+        max_per_host: cfg.max_per_host.max(1),
+        hosts: Arc::new(Mutex::new(BTreeMap::new())),
+    })
+}
+
+impl HttpClient {
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    // send the request once a slot of its host is free, and fail on error status
+    pub async fn send(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<Response, Box<dyn Error + Send + Sync>> {
+        let request = request.build()?;
+        let host = request.url().host_str().unwrap_or_default().to_string();
+        let _permit = self.semaphore(&host).acquire_owned().await?;
+        let response = self.client.execute(request).await?.error_for_status()?;
+        Ok(response)
+    }
+
+    fn semaphore(&self, host: &str) -> Arc<Semaphore> {
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts
+            .entry(host.into())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_host)))
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
+    use futures::future;
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test]
+    async fn test_per_host_limit() {
+        let (slow_url, peak) = mock::serve_slow(100).await;
+        let http = new(&HttpConfig {
+            max_per_host: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let requests = (0..5).map(|_| http.send(http.get(&format!("{}/ok", slow_url))));
+        let responses = future::join_all(requests).await;
+        assert!(responses.iter().all(|response| response.is_ok()));
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(http.semaphore("127.0.0.1").available_permits(), 2);
+        let url = mock::serve(vec![mock::route("/ok", StatusCode::OK, "ok")]).await;
+        let err = http
+            .send(http.get(&format!("{}/missing", url)))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("404"));
+    }
+}
@@ -0,0 +1,109 @@
+use super::http::HttpClient;
+use super::Exchange;
+use crate::fetcher::{PairInfo, VolumeUnit};
+use async_trait::async_trait;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::error::Error;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub status: String,
+    pub ts: u64,
+    #[serde(default)]
+    pub data: Vec<Pair>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Pair {
+    pub symbol: String,
+    pub close: f64,
+    pub amount: f64, //volume in base currency
+    pub vol: f64,    //volume in quote currency
+    pub bid: f64,
+    pub ask: f64,
+}
+
+pub struct Huobi {
+    pub base_url: String,
+    pub http: HttpClient,
+}
+
+impl Default for Huobi {
+    fn default() -> Self {
+        Huobi {
+            base_url: BASE_URL.into(),
+            http: HttpClient::default(),
+        }
+    }
+}
+
+pub const NAME: &str = "huobi";
+pub const BASE_URL: &str = "https://api.huobi.pro";
+
+#[async_trait]
+impl Exchange for Huobi {
+    // symbols are in lower case, e.g. btcusdt
+    async fn get_pairs(
+        &self,
+        symbols: Vec<String>,
+    ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+        let request_url = format!("{}/market/tickers", self.base_url);
+        let response = self.http.send(self.http.get(&request_url)).await?;
+        let rsps: Response = response.json().await?;
+        if rsps.status != "ok" {
+            return Err(format!("status {}", rsps.status).into());
+        }
+        let mut result = Vec::<PairInfo>::new();
+        for pair in &rsps.data {
+            if symbols.contains(&pair.symbol) {
+                result.push(PairInfo {
+                    symbol: pair.symbol.clone(),
+                    price: pair.close,
+                    volume: pair.amount,
+                    timestamp: rsps.ts,
+                    exchange: NAME.into(),
+                    depth: None,
+                    volume_unit: VolumeUnit::of_pair(&pair.symbol),
+                    bid: Some(pair.bid),
+                    ask: Some(pair.ask),
+                });
+            }
+        }
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::expression;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
+    #[tokio::test]
+    async fn test_fetch() {
+        let url = mock::serve(vec![mock::route(
+            "/market/tickers",
+            StatusCode::OK,
+            include_str!("../../fixtures/huobi/tickers.json"),
+        )])
+        .await;
+        let huobi = Huobi {
+            base_url: url,
+            ..Default::default()
+        };
+        let symbols = expression::parse_symbols(&vec!["btcusdt".into()]).unwrap();
+        let legs = huobi
+            .get_pairs(expression::expand_symbols(&symbols))
+            .await
+            .unwrap();
+        let (result, _) = expression::reduce_symbols(&symbols, &legs);
+        println!("{:?}", result);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].price, 23008.2);
+        assert_eq!(result[0].volume, 1500.987654);
+        assert_eq!(result[0].timestamp, 1660000000000);
+    }
+}
@@ -0,0 +1,131 @@
+use super::http::HttpClient;
+use super::Exchange;
+use crate::fetcher::{self, PairInfo, VolumeUnit};
+use async_trait::async_trait;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub error: Vec<String>,
+    #[serde(default)]
+    pub result: BTreeMap<String, Pair>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pair {
+    pub a: Vec<String>, //ask price, whole lot volume, lot volume
+    pub b: Vec<String>, //bid price, whole lot volume, lot volume
+    pub c: Vec<String>, //last trade price, lot volume
+    pub v: Vec<String>, //volume of today, and of the last 24 hours, in base currency
+    pub p: Vec<String>, //volume weighted average price of today, and of the last 24 hours
+}
+
+pub struct Kraken {
+    pub base_url: String,
+    pub http: HttpClient,
+}
+
+impl Default for Kraken {
+    fn default() -> Self {
+        Kraken {
+            base_url: BASE_URL.into(),
+            http: HttpClient::default(),
+        }
+    }
+}
+
+pub const NAME: &str = "kraken";
+
+// base_asset reads the base asset of a pair name, legacy names like XXBTZUSD prefix assets by X or Z
+fn base_asset(symbol: &str) -> Option<String> {
+    match symbol.get(4..5) {
+        Some("Z") if symbol.len() == 8 && symbol.starts_with('X') => Some(symbol[1..4].into()),
+        _ => fetcher::base_asset(symbol),
+    }
+}
+pub const BASE_URL: &str = "https://api.kraken.com";
+
+#[async_trait]
+impl Exchange for Kraken {
+    // symbols are pair names returned by kraken, e.g. XXBTZUSD rather than XBTUSD,
+    // since the result is keyed by them
+    async fn get_pairs(
+        &self,
+        symbols: Vec<String>,
+    ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+        let request_url = format!(
+            "{}/0/public/Ticker?pair={}",
+            self.base_url,
+            symbols.join(",")
+        );
+        let response = self.http.send(self.http.get(&request_url)).await?;
+        let timestamp = super::server_time(&response).ok_or("no Date header in response")?;
+        let rsps: Response = response.json().await?;
+        if !rsps.error.is_empty() {
+            return Err(rsps.error.join(", ").into());
+        }
+        let mut result = Vec::<PairInfo>::new();
+        for (symbol, pair) in &rsps.result {
+            if symbols.contains(symbol) {
+                result.push(PairInfo {
+                    symbol: symbol.clone(),
+                    price: pair.c.first().ok_or("no last price")?.parse::<f64>()?,
+                    volume: pair.v.get(1).ok_or("no 24h volume")?.parse::<f64>()?,
+                    timestamp: timestamp,
+                    exchange: NAME.into(),
+                    depth: None,
+                    volume_unit: VolumeUnit::base(base_asset(symbol).as_deref()),
+                    bid: pair.b.first().and_then(|bid| bid.parse::<f64>().ok()),
+                    ask: pair.a.first().and_then(|ask| ask.parse::<f64>().ok()),
+                });
+            }
+        }
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::expression;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
+    #[tokio::test]
+    async fn test_fetch() {
+        let url = mock::serve(vec![
+            mock::route(
+                "pair=XXBTZUSD",
+                StatusCode::OK,
+                include_str!("../../fixtures/kraken/ticker.json"),
+            ),
+            mock::route(
+                "pair=XBTXXX",
+                StatusCode::OK,
+                include_str!("../../fixtures/kraken/error.json"),
+            ),
+        ])
+        .await;
+        let kraken = Kraken {
+            base_url: url,
+            ..Default::default()
+        };
+        let symbols =
+            expression::parse_symbols(&vec!["XXBTZUSD".into(), "XBTUSDT".into()]).unwrap();
+        let legs = kraken
+            .get_pairs(expression::expand_symbols(&symbols))
+            .await
+            .unwrap();
+        let (result, _) = expression::reduce_symbols(&symbols, &legs);
+        println!("{:?}", result);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].price, 23008.9);
+        assert_eq!(result[0].volume, 2950.12345678);
+        assert_eq!(result[0].volume_unit.asset, Some("XBT".into()));
+        let err = kraken.get_pairs(vec!["XBTXXX".into()]).await.unwrap_err();
+        assert!(err.to_string().contains("Unknown asset pair"));
+    }
+}
@@ -1,12 +1,15 @@
-use super::expression;
+use super::http::HttpClient;
+use super::stream::{self, StreamSpec, TickerCache};
 use super::Exchange;
-use super::PairInfo;
+use super::{PairInfo, VolumeUnit};
+use crate::processor::utils;
 use async_trait::async_trait;
-use reqwest::ClientBuilder;
+use futures::future;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use serde_json::{json, Value};
 use std::error::Error;
-use std::time::Duration;
+use strum_macros::Display;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -36,10 +39,52 @@ pub struct Pair {
     pub maker_coefficient: String,
 }
 
-#[derive(Default, Clone)]
-pub struct Kucoin {}
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulletResponse {
+    pub code: String,
+    pub data: Bullet,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bullet {
+    pub token: String,
+    pub instance_servers: Vec<InstanceServer>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceServer {
+    pub endpoint: String,
+    pub ping_interval: u64,
+}
+
+#[derive(Debug, Display)]
+pub enum KucoinError {
+    NoInstanceServer,
+}
+impl std::error::Error for KucoinError {}
+
+#[derive(Clone)]
+pub struct Kucoin {
+    pub base_url: String,
+    pub http: HttpClient,
+    pub stream: Option<TickerCache>, //read tickers from websocket if specified
+}
+
+impl Default for Kucoin {
+    fn default() -> Self {
+        Kucoin {
+            base_url: BASE_URL.into(),
+            http: HttpClient::default(),
+            stream: None,
+        }
+    }
+}
 
 pub const NAME: &str = "kucoin";
+pub const BASE_URL: &str = "https://api.kucoin.com";
 
 #[async_trait]
 impl Exchange for Kucoin {
@@ -47,45 +92,169 @@ impl Exchange for Kucoin {
         &self,
         symbols: Vec<String>,
     ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+        if let Some(cache) = &self.stream {
+            if let Some(crawl_result) = cache.get_all(&symbols) {
+                return Ok(crawl_result);
+            }
+        }
+        // stats are served per symbol, so symbols are fetched concurrently
+        let requests = symbols.iter().map(|symbol| self.get_pair(symbol));
+        future::try_join_all(requests).await
+    }
+
+    fn requests(&self, symbols: &Vec<String>) -> usize {
+        symbols.len()
+    }
+}
+
+impl Kucoin {
+    async fn get_pair(&self, symbol: &str) -> Result<PairInfo, Box<dyn Error + Send + Sync>> {
+        let request_url = format!(
+            "{base_url}/api/v1/market/stats?symbol={symbol}",
+            base_url = self.base_url,
+            symbol = symbol
+        );
+        let response = self.http.send(self.http.get(&request_url)).await?;
+        let rsps: Response = response.json().await?;
+        let pair = rsps.data;
+        Ok(PairInfo {
+            symbol: symbol.into(),
+            price: pair.last.parse::<f64>()?,
+            volume: pair.vol.parse::<f64>()?,
+            timestamp: pair.time as u64,
+            exchange: NAME.into(),
+            depth: None,
+            volume_unit: VolumeUnit::of_pair(&symbol),
+            bid: pair.buy.parse::<f64>().ok(),
+            ask: pair.sell.parse::<f64>().ok(),
+        })
+    }
+}
+
+// Snapshot channel of markets, the endpoint is assigned by applying a token first
+#[derive(Default)]
+pub struct TickerStream {
+    pub http: HttpClient, //the token is applied through the shared client
+}
+
+#[async_trait]
+impl StreamSpec for TickerStream {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    async fn endpoint(
+        &self,
+        _symbols: &Vec<String>,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let request_url = format!("{}/api/v1/bullet-public", BASE_URL);
+        let response = self.http.send(self.http.post(&request_url)).await?;
+        let rsps: BulletResponse = response.json().await?;
+        let server = rsps
+            .data
+            .instance_servers
+            .first()
+            .ok_or(KucoinError::NoInstanceServer)?;
+        Ok(format!("{}?token={}", server.endpoint, rsps.data.token))
+    }
+
+    fn subscribe_messages(&self, symbols: &Vec<String>) -> Vec<String> {
+        let msg = json!({
+            "id": utils::timestamp().to_string(),
+            "type": "subscribe",
+            "topic": format!("/market/snapshot:{}", symbols.join(",")),
+            "response": true,
+        });
+        vec![msg.to_string()]
+    }
+
+    fn parse(&self, msg: &str) -> Vec<PairInfo> {
         let mut result = Vec::<PairInfo>::new();
-        for symbol in expression::expand_symbols(&symbols) {
-            let request_url = format!(
-                "https://api.kucoin.com/api/v1/market/stats?symbol={symbol}",
-                symbol = symbol
-            );
-            let timeout = Duration::new(5, 0);
-            let client = ClientBuilder::new().timeout(timeout).build()?;
-            let response = client
-                .get(&request_url)
-                .header("User-Agent", "ti-oracle")
-                .send()
-                .await?;
-            let rsps: Response = response.json().await?;
-            //println!("{}", pair.time.clone());
-            let pair = rsps.data;
+        let msg: Value = match serde_json::from_str(msg) {
+            Ok(msg) => msg,
+            Err(_) => return result,
+        };
+        if msg["subject"].as_str() != Some("trade.snapshot") {
+            return result;
+        }
+        let pair = &msg["data"]["data"];
+        if let (Some(symbol), Some(price), Some(volume), Some(ts)) = (
+            pair["symbol"].as_str(),
+            stream::value_f64(&pair["lastTradedPrice"]),
+            stream::value_f64(&pair["vol"]),
+            stream::value_f64(&pair["datetime"]),
+        ) {
             result.push(PairInfo {
-                symbol: symbol.clone(),
-                price: pair.buy.parse::<f64>()?,
-                volume: pair.vol.parse::<f64>()?,
-                timestamp: pair.time as u64,
+                symbol: symbol.into(),
+                price: price,
+                volume: volume,
+                timestamp: ts as u64,
                 exchange: NAME.into(),
+                depth: None,
+                volume_unit: VolumeUnit::of_pair(&symbol),
+                bid: stream::value_f64(&pair["buy"]),
+                ask: stream::value_f64(&pair["sell"]),
             });
         }
-        let result = expression::reduce_symbols(&symbols, &result);
-        return Ok(result);
+        result
+    }
+
+    fn ping_message(&self) -> Option<String> {
+        Some(json!({"id": utils::timestamp().to_string(), "type": "ping"}).to_string())
     }
 }
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fetcher::expression;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
     #[tokio::test]
     async fn test_fetch() {
-        let kucoin = Kucoin::default();
-        let result = kucoin
-            .get_pairs(vec!["BTC-USDC".into(), "BTC-USDT".into()])
-            .await;
-        let result = result.unwrap();
+        let url = mock::serve(vec![
+            mock::route(
+                "symbol=BTC-USDC",
+                StatusCode::OK,
+                include_str!("../../fixtures/kucoin/BTC-USDC.json"),
+            ),
+            mock::route(
+                "symbol=BTC-USDT",
+                StatusCode::OK,
+                include_str!("../../fixtures/kucoin/BTC-USDT.json"),
+            ),
+            mock::route(
+                "symbol=USDT-USDC",
+                StatusCode::OK,
+                include_str!("../../fixtures/kucoin/USDT-USDC.json"),
+            ),
+        ])
+        .await;
+        let kucoin = Kucoin {
+            base_url: url,
+            ..Default::default()
+        };
+        let symbols =
+            expression::parse_symbols(&vec!["BTC-USDC".into(), "BTC-USDT mul USDT-USDC".into()])
+                .unwrap();
+        let legs = kucoin
+            .get_pairs(expression::expand_symbols(&symbols))
+            .await
+            .unwrap();
+        let (result, _) = expression::reduce_symbols(&symbols, &legs);
         println!("{:?}", result);
         assert_eq!(result.len(), 2);
+        assert_eq!(result[0].price, 23006.3);
+        assert!((result[1].price - 23009.9 * 0.9999).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let msg = r#"{"type":"message","topic":"/market/snapshot:BTC-USDT","subject":"trade.snapshot","data":{"sequence":"1","data":{"symbol":"BTC-USDT","lastTradedPrice":23000.1,"vol":1234.5,"datetime":1660000000000}}}"#;
+        let result = TickerStream::default().parse(msg);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].price, 23000.1);
+        assert_eq!(result[0].timestamp, 1660000000000);
+        assert_eq!(TickerStream::default().parse(r#"{"type":"pong"}"#).len(), 0);
     }
 }
@@ -0,0 +1,248 @@
+use axum::{handler::Handler, http::StatusCode, http::Uri, Router};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Route answers requests whose "path?query body" contains the pattern
+pub struct Route {
+    pub pattern: String,
+    pub status: StatusCode,
+    pub body: String,
+}
+
+pub fn route(pattern: &str, status: StatusCode, body: &str) -> Route {
+    Route {
+        pattern: pattern.into(),
+        status: status,
+        body: body.into(),
+    }
+}
+
+// start a local http server replaying recorded responses, and return its base url
+pub async fn serve(routes: Vec<Route>) -> String {
+    let routes = Arc::new(routes);
+    let handler = move |uri: Uri, body: String| {
+        let routes = routes.clone();
+        async move {
+            let request = format!("{} {}", uri, body);
+            for route in routes.iter() {
+                if request.contains(&route.pattern) {
+                    return (route.status, route.body.clone());
+                }
+            }
+            (StatusCode::NOT_FOUND, "".to_string())
+        }
+    };
+    let app = Router::new().fallback(handler.into_service());
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let server = axum::Server::bind(&addr).serve(app.into_make_service());
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    url
+}
+
+// start a local http server answering every request after a delay, and return its base url
+// with the peak number of requests it served at the same time
+pub async fn serve_slow(delay: u64) -> (String, Arc<AtomicUsize>) {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let handler = {
+        let peak = peak.clone();
+        move || {
+            let in_flight = in_flight.clone();
+            let peak = peak.clone();
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                "ok"
+            }
+        }
+    };
+    let app = Router::new().fallback(handler.into_service());
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let server = axum::Server::bind(&addr).serve(app.into_make_service());
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    (url, peak)
+}
+
+// routes of all recorded responses, which cover pairs of the feed in config/node.yaml
+pub fn fixtures() -> Vec<Route> {
+    vec![
+        route(
+            "/api/v3/ticker/24hr",
+            StatusCode::OK,
+            include_str!("../../fixtures/binance/ticker_24hr.json"),
+        ),
+        route(
+            "/v2/tickers",
+            StatusCode::OK,
+            include_str!("../../fixtures/bitfinex/tickers.json"),
+        ),
+        route(
+            "/v5/market/tickers?category=spot",
+            StatusCode::OK,
+            include_str!("../../fixtures/bybit/tickers.json"),
+        ),
+        route(
+            "/api/v4/spot/tickers",
+            StatusCode::OK,
+            include_str!("../../fixtures/gateio/tickers.json"),
+        ),
+        route(
+            "/0/public/Ticker",
+            StatusCode::OK,
+            include_str!("../../fixtures/kraken/ticker.json"),
+        ),
+        route(
+            "/products/BTC-USD/ticker",
+            StatusCode::OK,
+            include_str!("../../fixtures/coinbase/BTC-USD.json"),
+        ),
+        route(
+            "/products/BTC-USDT/ticker",
+            StatusCode::OK,
+            include_str!("../../fixtures/coinbase/BTC-USDT.json"),
+        ),
+        route(
+            "/products/USDT-USD/ticker",
+            StatusCode::OK,
+            include_str!("../../fixtures/coinbase/USDT-USD.json"),
+        ),
+        route(
+            "symbol=BTC-USDC",
+            StatusCode::OK,
+            include_str!("../../fixtures/kucoin/BTC-USDC.json"),
+        ),
+        route(
+            "symbol=BTC-USDT",
+            StatusCode::OK,
+            include_str!("../../fixtures/kucoin/BTC-USDT.json"),
+        ),
+        route(
+            "symbol=USDT-USDC",
+            StatusCode::OK,
+            include_str!("../../fixtures/kucoin/USDT-USDC.json"),
+        ),
+        route(
+            "/api/v5/market/tickers",
+            StatusCode::OK,
+            include_str!("../../fixtures/okex/tickers.json"),
+        ),
+        route(
+            "0x397ff1542f962076d0bfe58ea045ffa2d347aca0",
+            StatusCode::OK,
+            include_str!(
+                "../../fixtures/sushiswap/0x397ff1542f962076d0bfe58ea045ffa2d347aca0.json"
+            ),
+        ),
+        route(
+            "0xceff51756c56ceffca006cd410b03ffc46dd3a58",
+            StatusCode::OK,
+            include_str!(
+                "../../fixtures/sushiswap/0xceff51756c56ceffca006cd410b03ffc46dd3a58.json"
+            ),
+        ),
+        route(
+            "0x004375dff511095cc5a197a54140a24efef3a416",
+            StatusCode::OK,
+            include_str!(
+                "../../fixtures/uniswapv2/0x004375dff511095cc5a197a54140a24efef3a416.json"
+            ),
+        ),
+        route(
+            "0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc",
+            StatusCode::OK,
+            include_str!(
+                "../../fixtures/uniswapv2/0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc.json"
+            ),
+        ),
+        route(
+            "0xbb2b8038a1640196fbe3e38816f3e67cba72d940",
+            StatusCode::OK,
+            include_str!(
+                "../../fixtures/uniswapv2/0xbb2b8038a1640196fbe3e38816f3e67cba72d940.json"
+            ),
+        ),
+        route(
+            "0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8",
+            StatusCode::OK,
+            include_str!(
+                "../../fixtures/uniswapv3/0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8.json"
+            ),
+        ),
+        route(
+            "0x99ac8ca7087fa4a2a1fb6357269965a2014abc35",
+            StatusCode::OK,
+            include_str!(
+                "../../fixtures/uniswapv3/0x99ac8ca7087fa4a2a1fb6357269965a2014abc35.json"
+            ),
+        ),
+        route(
+            "0xcbcdf9626bc03e24f779434178a73a0b4bad62ed",
+            StatusCode::OK,
+            include_str!(
+                "../../fixtures/uniswapv3/0xcbcdf9626bc03e24f779434178a73a0b4bad62ed.json"
+            ),
+        ),
+        // after okex, whose path contains this one
+        route(
+            "/market/tickers",
+            StatusCode::OK,
+            include_str!("../../fixtures/huobi/tickers.json"),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::binance::Binance;
+    use crate::fetcher::coinbase::Coinbase;
+    use crate::fetcher::kucoin::Kucoin;
+    use crate::fetcher::okex::OkEx;
+    use crate::fetcher::Exchange;
+
+    #[tokio::test]
+    async fn test_error_paths() {
+        let url = serve(vec![
+            route("/api/v3/ticker/24hr", StatusCode::TOO_MANY_REQUESTS, "{}"),
+            route("/products/", StatusCode::INTERNAL_SERVER_ERROR, ""),
+            route("/api/v5/market/tickers", StatusCode::OK, "{\"code\":"),
+            route(
+                "/api/v1/market/stats",
+                StatusCode::OK,
+                include_str!("../../fixtures/kucoin/stats_missing.json"),
+            ),
+        ])
+        .await;
+        let binance = Binance {
+            base_url: url.clone(),
+            ..Default::default()
+        };
+        let err = binance.get_pairs(vec!["BTCUSDT".into()]).await.unwrap_err();
+        assert!(err.to_string().contains("429"));
+        let coinbase = Coinbase {
+            base_url: url.clone(),
+            ..Default::default()
+        };
+        let err = coinbase
+            .get_pairs(vec!["BTC-USD".into()])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("500"));
+        let okex = OkEx {
+            base_url: url.clone(),
+            ..Default::default()
+        };
+        assert!(okex.get_pairs(vec!["BTC-USDT".into()]).await.is_err());
+        let kucoin = Kucoin {
+            base_url: url.clone(),
+            ..Default::default()
+        };
+        assert!(kucoin.get_pairs(vec!["XXX-USDT".into()]).await.is_err());
+    }
+}
@@ -1,15 +1,28 @@
 pub mod aggregator;
 pub mod binance;
+pub mod bitfinex;
+pub mod bybit;
 pub mod coinbase;
+pub mod curve;
 pub mod expression;
-pub mod ftx;
+pub mod gateio;
+pub mod http;
+pub mod huobi;
+pub mod kraken;
 pub mod kucoin;
+#[cfg(test)]
+pub mod mock;
 pub mod okex;
+pub mod pool;
+pub mod resilience;
+pub mod rest;
+pub mod stream;
 pub mod sushiswap;
 pub mod uniswapv2;
 pub mod uniswapv3;
 
 use async_trait::async_trait;
+use chrono::DateTime;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 const PRECESIONS_REPRESENT: f64 = 1e8;
@@ -21,12 +34,124 @@ pub struct PairInfo {
     pub volume: f64,
     pub timestamp: u64,
     pub exchange: String,
+    #[serde(default)]
+    pub depth: Option<f64>, //liquidity of the market in base token, if known
+    #[serde(default)]
+    pub volume_unit: VolumeUnit,
+    #[serde(default)]
+    pub bid: Option<f64>, //best bid, if reported by the source
+    #[serde(default)]
+    pub ask: Option<f64>, //best ask, if reported by the source
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Base,
+    Quote,
+}
+
+impl Default for Side {
+    fn default() -> Self {
+        Side::Base
+    }
+}
+
+// VolumeUnit tells which asset `volume` of a pair is counted in
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VolumeUnit {
+    pub side: Side,
+    pub asset: Option<String>, //e.g. WBTC, if reported by the source
+}
+
+impl VolumeUnit {
+    pub fn base(asset: Option<&str>) -> Self {
+        VolumeUnit {
+            side: Side::Base,
+            asset: asset.map(|asset| asset.into()),
+        }
+    }
+
+    pub fn quote(asset: Option<&str>) -> Self {
+        VolumeUnit {
+            side: Side::Quote,
+            asset: asset.map(|asset| asset.into()),
+        }
+    }
+
+    // of_pair is the base volume of a CEX pair, counted in the base asset read from the pair name
+    pub fn of_pair(symbol: &str) -> Self {
+        VolumeUnit::base(base_asset(symbol).as_deref())
+    }
+}
+
+// quote assets of CEX pairs, longer ones first so that `BTCUSDT` is not split as `BTCUSD` and `T`
+const QUOTE_ASSETS: &[&str] = &[
+    "FDUSD", "USDT", "USDC", "BUSD", "TUSD", "UST", "DAI", "USD", "EUR", "GBP", "BTC", "ETH",
+];
+
+// base_asset reads the base asset from the name of a CEX pair, like BTC of `BTC-USDT`, `BTC_USDT`,
+// `tBTC:USD` or `btcusdt`, it is unknown if the name can not be split
+pub fn base_asset(symbol: &str) -> Option<String> {
+    let symbol = symbol.to_uppercase();
+    let base = match symbol.split_once(|c| c == '-' || c == '_' || c == '/' || c == ':') {
+        Some((base, _)) => base,
+        None => QUOTE_ASSETS
+            .iter()
+            .find_map(|quote| symbol.strip_suffix(quote))?,
+    };
+    (!base.is_empty()).then(|| base.to_string())
+}
+
+// base_volume converts volume of the pair into its base asset, by the price of the pair
+pub fn base_volume(pair: &PairInfo) -> f64 {
+    match pair.volume_unit.side {
+        Side::Base => pair.volume,
+        Side::Quote => pair.volume / pair.price,
+    }
+}
+
+// mid_price is the middle of the best bid and ask, if both are reported
+pub fn mid_price(pair: &PairInfo) -> Option<f64> {
+    match (pair.bid, pair.ask) {
+        (Some(bid), Some(ask)) if bid > 0.0 && ask >= bid => Some((bid + ask) / 2.0),
+        _ => None,
+    }
+}
+
+// spread is the gap between the best bid and ask, relative to the mid price
+pub fn spread(pair: &PairInfo) -> Option<f64> {
+    let mid = mid_price(pair)?;
+    Some((pair.ask? - pair.bid?) / mid)
+}
+
+// server_time returns the unix timestamp in ms from the Date header of a response,
+// for sources which do not report the time of their quotes
+pub fn server_time(response: &reqwest::Response) -> Option<u64> {
+    let date = response
+        .headers()
+        .get(reqwest::header::DATE)?
+        .to_str()
+        .ok()?;
+    let time = DateTime::parse_from_rfc2822(date).ok()?;
+    Some(time.timestamp_millis() as u64)
+}
+
+// Exchange fetches quotes of plain symbols, expressions are evaluated by the aggregator
 #[async_trait]
 pub trait Exchange: Send + Sync {
     async fn get_pairs(
         &self,
         symbols: Vec<String>,
     ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>>;
+
+    // requests sent to fetch the symbols, counted by rate limiting
+    fn requests(&self, _symbols: &Vec<String>) -> usize {
+        1
+    }
+
+    // validate checks the symbols against the source before the feed starts, e.g. tokens of DEX pools
+    async fn validate(&self, _symbols: &Vec<String>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
 }
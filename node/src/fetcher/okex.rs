@@ -1,13 +1,13 @@
-use super::expression;
+use super::http::HttpClient;
+use super::stream::{self, StreamSpec, TickerCache};
 use super::Exchange;
-use crate::fetcher::PairInfo;
+use crate::fetcher::{PairInfo, VolumeUnit};
 use async_trait::async_trait;
 use eyre::Result;
-use reqwest::ClientBuilder;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use serde_json::{json, Value};
 use std::error::Error;
-use std::time::Duration;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -38,10 +38,25 @@ pub struct Pair {
     pub sod_utc8: String,
 }
 
-#[derive(Default, Clone)]
-pub struct OkEx {}
+#[derive(Clone)]
+pub struct OkEx {
+    pub base_url: String,
+    pub http: HttpClient,
+    pub stream: Option<TickerCache>, //read tickers from websocket if specified
+}
+
+impl Default for OkEx {
+    fn default() -> Self {
+        OkEx {
+            base_url: BASE_URL.into(),
+            http: HttpClient::default(),
+            stream: None,
+        }
+    }
+}
 
 pub const NAME: &str = "okex";
+pub const BASE_URL: &str = "https://www.okex.com";
 
 #[async_trait]
 impl Exchange for OkEx {
@@ -49,40 +64,134 @@ impl Exchange for OkEx {
         &self,
         symbols: Vec<String>,
     ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
-        let request_url = format!("https://www.okex.com/api/v5/market/tickers?instType=SPOT");
-        let timeout = Duration::new(5, 0);
-        let client = ClientBuilder::new().timeout(timeout).gzip(true).build()?;
-        let response = client.get(&request_url).send().await?;
+        if let Some(cache) = &self.stream {
+            if let Some(crawl_result) = cache.get_all(&symbols) {
+                return Ok(crawl_result);
+            }
+        }
+        let request_url = format!("{}/api/v5/market/tickers?instType=SPOT", self.base_url);
+        let response = self.http.send(self.http.get(&request_url)).await?;
         let pair_list: Response = response.json().await?;
         let mut crawl_result = Vec::<PairInfo>::new();
-        let expand_pairs = expression::expand_symbols(&symbols);
         for pair in &pair_list.data {
-            if symbols.contains(&pair.inst_id) || expand_pairs.contains(&pair.inst_id) {
+            if symbols.contains(&pair.inst_id) {
                 crawl_result.push(PairInfo {
                     symbol: pair.inst_id.clone(),
                     price: pair.last.parse::<f64>()?,
                     volume: pair.vol24h.parse::<f64>()?,
                     timestamp: pair.ts.parse::<u64>()?,
                     exchange: NAME.into(),
+                    depth: None,
+                    volume_unit: VolumeUnit::of_pair(&pair.inst_id),
+                    bid: pair.bid_px.parse::<f64>().ok(),
+                    ask: pair.ask_px.parse::<f64>().ok(),
                 });
             }
         }
-        let result = expression::reduce_symbols(&symbols, &crawl_result);
-        return Ok(result);
+        return Ok(crawl_result);
+    }
+}
+
+// Tickers channel of public websocket
+pub struct TickerStream {}
+
+#[async_trait]
+impl StreamSpec for TickerStream {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    async fn endpoint(
+        &self,
+        _symbols: &Vec<String>,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok("wss://ws.okx.com:8443/ws/v5/public".into())
+    }
+
+    fn subscribe_messages(&self, symbols: &Vec<String>) -> Vec<String> {
+        let args: Vec<Value> = symbols
+            .iter()
+            .map(|symbol| json!({"channel": "tickers", "instId": symbol}))
+            .collect();
+        vec![json!({"op": "subscribe", "args": args}).to_string()]
+    }
+
+    fn parse(&self, msg: &str) -> Vec<PairInfo> {
+        let mut result = Vec::<PairInfo>::new();
+        let msg: Value = match serde_json::from_str(msg) {
+            Ok(msg) => msg,
+            Err(_) => return result, //e.g. pong
+        };
+        if let Some(data) = msg["data"].as_array() {
+            for pair in data {
+                if let (Some(symbol), Some(price), Some(volume), Some(ts)) = (
+                    pair["instId"].as_str(),
+                    stream::value_f64(&pair["last"]),
+                    stream::value_f64(&pair["vol24h"]),
+                    stream::value_f64(&pair["ts"]),
+                ) {
+                    result.push(PairInfo {
+                        symbol: symbol.into(),
+                        price: price,
+                        volume: volume,
+                        timestamp: ts as u64,
+                        exchange: NAME.into(),
+                        depth: None,
+                        volume_unit: VolumeUnit::of_pair(&symbol),
+                        bid: stream::value_f64(&pair["bidPx"]),
+                        ask: stream::value_f64(&pair["askPx"]),
+                    });
+                }
+            }
+        }
+        result
+    }
+
+    // connection is closed if there is no data for 30 seconds
+    fn ping_message(&self) -> Option<String> {
+        Some("ping".into())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fetcher::expression;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
     #[tokio::test]
     async fn test_fetch() {
-        let okex = OkEx::default();
-        let result = okex
-            .get_pairs(vec!["BTC-USDC".into(), "BTC-USDT".into()])
-            .await;
-        let result = result.unwrap();
+        let url = mock::serve(vec![mock::route(
+            "/api/v5/market/tickers?instType=SPOT",
+            StatusCode::OK,
+            include_str!("../../fixtures/okex/tickers.json"),
+        )])
+        .await;
+        let okex = OkEx {
+            base_url: url,
+            ..Default::default()
+        };
+        let symbols =
+            expression::parse_symbols(&vec!["BTC-USDC".into(), "BTC-USDT div USDC-USDT".into()])
+                .unwrap();
+        let legs = okex
+            .get_pairs(expression::expand_symbols(&symbols))
+            .await
+            .unwrap();
+        let (result, _) = expression::reduce_symbols(&symbols, &legs);
         println!("{:?}", result);
         assert_eq!(result.len(), 2);
+        assert_eq!(result[0].volume, 812.1123);
+        assert!((result[1].price - 23009.8 / 1.0002).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let msg = r#"{"arg":{"channel":"tickers","instId":"BTC-USDT"},"data":[{"instType":"SPOT","instId":"BTC-USDT","last":"23000.1","vol24h":"1234.5","ts":"1660000000000"}]}"#;
+        let result = TickerStream {}.parse(msg);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].symbol, "BTC-USDT");
+        assert_eq!(result[0].timestamp, 1660000000000);
+        assert_eq!(TickerStream {}.parse("pong").len(), 0);
     }
 }
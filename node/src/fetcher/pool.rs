@@ -0,0 +1,156 @@
+use super::{PairInfo, VolumeUnit};
+use crate::chains::dex::{self, ChainReader, PoolState};
+use ethers::prelude::Address;
+use std::error::Error;
+use strum_macros::Display;
+
+#[derive(Debug, Display, PartialEq)]
+pub enum PoolError {
+    MissingToken(String),
+}
+impl std::error::Error for PoolError {}
+
+// PoolSpec is a DEX leg in mappings, the address of a pool optionally followed by its base token,
+// e.g. `0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8:WETH`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolSpec {
+    pub address: String,
+    pub base: Option<String>,
+}
+
+pub fn parse_spec(symbol: &str) -> PoolSpec {
+    match symbol.split_once(':') {
+        Some((address, base)) => PoolSpec {
+            address: address.into(),
+            base: Some(base.into()),
+        },
+        None => PoolSpec {
+            address: symbol.into(),
+            base: None,
+        },
+    }
+}
+
+// same_asset tells whether a token is the asset, wrapped tokens like WBTC are taken as BTC,
+// and so is XBT, the name of BTC on some exchanges
+pub fn same_asset(token: &str, asset: &str) -> bool {
+    let canonical = |name: &str| match name.to_uppercase().as_str() {
+        "XBT" => "BTC".to_string(),
+        name => name.to_string(),
+    };
+    let (token, asset) = (canonical(token), canonical(asset));
+    token == asset
+        || token
+            .strip_prefix('W')
+            .map_or(false, |unwrapped| canonical(unwrapped) == asset)
+}
+
+// base_index finds which token of the pool is the base of the leg, the one specified,
+// or the coin of the feed if not specified
+pub fn base_index(spec: &PoolSpec, coin: &str, tokens: &[String; 2]) -> Result<usize, PoolError> {
+    let asset = spec.base.as_deref().unwrap_or(coin);
+    tokens
+        .iter()
+        .position(|token| token.eq_ignore_ascii_case(asset))
+        .or_else(|| tokens.iter().position(|token| same_asset(token, asset)))
+        .ok_or_else(|| {
+            PoolError::MissingToken(format!(
+                "{} not in pool {} of {}/{}",
+                asset, spec.address, tokens[0], tokens[1]
+            ))
+        })
+}
+
+// pair_info sees the pool from its base token, the price of token0 is inverted if token1 is the base,
+// while volume is kept in token0 and marked as the quote one
+pub fn pair_info(
+    symbol: &str,
+    coin: &str,
+    state: &PoolState,
+    exchange: &str,
+    timestamp: u64,
+) -> Result<PairInfo, PoolError> {
+    let spec = parse_spec(symbol);
+    let base = base_index(&spec, coin, &state.tokens)?;
+    let (price, volume_unit) = match base {
+        0 => (state.price, VolumeUnit::base(Some(&state.tokens[0]))),
+        _ => (1.0 / state.price, VolumeUnit::quote(Some(&state.tokens[0]))),
+    };
+    Ok(PairInfo {
+        symbol: symbol.into(),
+        price: price,
+        volume: state.volume,
+        timestamp: timestamp,
+        exchange: exchange.into(),
+        depth: Some(state.depths[base]),
+        volume_unit: volume_unit,
+        bid: None,
+        ask: None,
+    })
+}
+
+// volume_24h sums up volume of token0 in the 24 hours before the latest indexed block, from hourly data of a pool,
+// a malformed timestamp of zero takes all hours in
+pub fn volume_24h<'a>(
+    hours: impl Iterator<Item = (u64, &'a str)>,
+    now: u64,
+) -> Result<f64, Box<dyn Error + Send + Sync>> {
+    let since = now.saturating_sub(24 * 3600);
+    let mut volume = 0.0;
+    for (start, hour_volume) in hours {
+        if start >= since {
+            volume += hour_volume.parse::<f64>()?;
+        }
+    }
+    Ok(volume)
+}
+
+// validate_onchain checks that every pool contains the base token of its leg, by reading tokens of pools
+pub async fn validate_onchain(
+    chain: &ChainReader,
+    coin: &str,
+    symbols: &Vec<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let provider = chain.provider()?;
+    for symbol in symbols {
+        let spec = parse_spec(symbol);
+        let tokens = dex::pool_tokens(provider.clone(), spec.address.parse::<Address>()?).await?;
+        base_index(&spec, coin, &tokens)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_base_index() {
+        let tokens: [String; 2] = ["USDC".into(), "WETH".into()];
+        let spec = |symbol: &str| parse_spec(symbol);
+        assert_eq!(spec("0xabc:WETH").base, Some("WETH".into()));
+        assert_eq!(spec("0xabc").address, "0xabc");
+        assert_eq!(base_index(&spec("0xabc"), "ETH", &tokens), Ok(1));
+        assert_eq!(base_index(&spec("0xabc:usdc"), "ETH", &tokens), Ok(0));
+        assert!(base_index(&spec("0xabc"), "BTC", &tokens).is_err());
+        assert!(same_asset("WBTC", "XBT"));
+        assert!(!same_asset("WETH", "BTC"));
+        let state = PoolState {
+            tokens: tokens.clone(),
+            price: 0.0005,
+            volume: 1000.0,
+            depths: [4000.0, 2.0],
+        };
+        let pair = pair_info("0xabc", "ETH", &state, "tiex", 0).unwrap();
+        assert_eq!(pair.price, 2000.0);
+        assert_eq!(pair.depth, Some(2.0));
+        assert_eq!(pair.volume_unit, VolumeUnit::quote(Some("USDC")));
+    }
+
+    #[test]
+    fn test_volume_24h() {
+        let hours = vec![(0, "1.5"), (100000 - 3600, "2.0"), (100000, "0.5")];
+        assert_eq!(volume_24h(hours.clone().into_iter(), 100000).unwrap(), 2.5);
+        assert_eq!(volume_24h(hours.into_iter(), 0).unwrap(), 4.0);
+        assert!(volume_24h(vec![(0, "x")].into_iter(), 0).is_err());
+    }
+}
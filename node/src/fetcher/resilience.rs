@@ -0,0 +1,324 @@
+use super::{Exchange, PairInfo};
+use crate::flags::{RateLimit, ResilienceConfig};
+use crate::processor::utils;
+use crate::processor::web::SharedState;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::time::Duration;
+use strum_macros::Display;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,   //calls go through
+    Open,     //calls are rejected until open_until
+    HalfOpen, //one call is probing whether the exchange recovers
+}
+
+#[derive(Debug, Display)]
+pub enum ResilienceError {
+    CircuitOpen,
+}
+impl std::error::Error for ResilienceError {}
+
+// ExchangeHealth is kept per exchange and shared by all feeds, since limits of venues are per client
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExchangeHealth {
+    pub state: BreakerState,
+    pub failures: u32,   //consecutive failed calls
+    pub open_until: u64, //in ms
+    pub last_error: String,
+    pub tokens: f64, //left in the bucket of rate limiting
+    #[serde(skip)]
+    refilled_at: u64,
+}
+
+impl ExchangeHealth {
+    fn new(limit: RateLimit, now: u64) -> Self {
+        ExchangeHealth {
+            state: BreakerState::Closed,
+            failures: 0,
+            open_until: 0,
+            last_error: "".into(),
+            tokens: limit.burst,
+            refilled_at: now,
+        }
+    }
+
+    // take tokens of the cost from the bucket, or return ms to wait before there are enough
+    fn take(&mut self, limit: RateLimit, cost: f64, now: u64) -> u64 {
+        let elapsed = now.saturating_sub(self.refilled_at) as f64 / 1000.0;
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.refilled_at = now;
+        let cost = cost.min(limit.burst);
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            return 0;
+        }
+        ((cost - self.tokens) / limit.rate * 1000.0).ceil() as u64
+    }
+}
+
+// published limits of public market APIs, in calls of `get_pairs`
+pub fn published_limit(exchange: &str) -> RateLimit {
+    match exchange {
+        "binance" => RateLimit {
+            rate: 0.5, //24hr tickers of all symbols weight 40, out of 1200 per minute
+            burst: 2.0,
+        },
+        "coinbase" => RateLimit {
+            rate: 10.0,
+            burst: 15.0,
+        },
+        "kucoin" => RateLimit {
+            rate: 10.0,
+            burst: 30.0,
+        },
+        "okex" => RateLimit {
+            rate: 10.0,
+            burst: 20.0,
+        },
+        "kraken" => RateLimit {
+            rate: 1.0,
+            burst: 15.0,
+        },
+        "bitfinex" => RateLimit {
+            rate: 1.5, //90 per minute
+            burst: 10.0,
+        },
+        "bybit" => RateLimit {
+            rate: 10.0,
+            burst: 20.0,
+        },
+        "gateio" => RateLimit {
+            rate: 20.0, //200 per 10 seconds
+            burst: 20.0,
+        },
+        "huobi" => RateLimit {
+            rate: 10.0,
+            burst: 20.0,
+        },
+        _ => RateLimit {
+            rate: 5.0,
+            burst: 10.0,
+        },
+    }
+}
+
+// get_pairs calls the exchange behind its circuit breaker and rate limiter, retrying transient errors
+pub async fn get_pairs(
+    cfg: &ResilienceConfig,
+    s_state: &SharedState,
+    name: &str,
+    exchange: &dyn Exchange,
+    symbols: Vec<String>,
+) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+    admit(cfg, s_state, name)?;
+    let mut attempt = 0;
+    let result = loop {
+        acquire(cfg, s_state, name, exchange.requests(&symbols) as f64).await;
+        match exchange.get_pairs(symbols.clone()).await {
+            Err(err) if attempt < cfg.retries && retryable(err.as_ref()) => {
+                tokio::time::sleep(backoff(cfg.backoff, attempt)).await;
+                attempt += 1;
+            }
+            result => break result,
+        }
+    };
+    record(cfg, s_state, name, &result);
+    result
+}
+
+// reject the call if the breaker is open, or let one call probe once it cools down
+fn admit(cfg: &ResilienceConfig, s_state: &SharedState, name: &str) -> Result<(), ResilienceError> {
+    let now = utils::timestamp();
+    let mut s_state = s_state.lock().unwrap();
+    let health = s_state
+        .exchange_health
+        .entry(name.into())
+        .or_insert_with(|| ExchangeHealth::new(cfg.rate_limit(name), now));
+    match health.state {
+        BreakerState::Closed => Ok(()),
+        BreakerState::Open if now >= health.open_until => {
+            health.state = BreakerState::HalfOpen;
+            Ok(())
+        }
+        _ => Err(ResilienceError::CircuitOpen),
+    }
+}
+
+async fn acquire(cfg: &ResilienceConfig, s_state: &SharedState, name: &str, cost: f64) {
+    let limit = cfg.rate_limit(name);
+    loop {
+        let wait = {
+            let now = utils::timestamp();
+            let mut s_state = s_state.lock().unwrap();
+            let health = s_state
+                .exchange_health
+                .entry(name.into())
+                .or_insert_with(|| ExchangeHealth::new(limit, now));
+            health.take(limit, cost, now)
+        };
+        if wait == 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(wait)).await;
+    }
+}
+
+fn record(
+    cfg: &ResilienceConfig,
+    s_state: &SharedState,
+    name: &str,
+    result: &Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>>,
+) {
+    let now = utils::timestamp();
+    let mut s_state = s_state.lock().unwrap();
+    let health = s_state
+        .exchange_health
+        .entry(name.into())
+        .or_insert_with(|| ExchangeHealth::new(cfg.rate_limit(name), now));
+    match result {
+        Ok(_) => {
+            health.state = BreakerState::Closed;
+            health.failures = 0;
+        }
+        Err(err) => {
+            health.failures += 1;
+            health.last_error = err.to_string();
+            if health.state == BreakerState::HalfOpen || health.failures >= cfg.failure_threshold {
+                health.state = BreakerState::Open;
+                health.open_until = now + cfg.open_duration * 1000;
+            }
+        }
+    }
+}
+
+// timeouts, broken connections, throttling and server errors are worth retrying
+fn retryable(err: &(dyn Error + Send + Sync + 'static)) -> bool {
+    match err.downcast_ref::<reqwest::Error>() {
+        Some(err) => {
+            err.is_timeout()
+                || err.is_connect()
+                || err
+                    .status()
+                    .map(|status| status.as_u16() == 429 || status.is_server_error())
+                    .unwrap_or(false)
+        }
+        None => false,
+    }
+}
+
+// exponential backoff with full jitter, so nodes do not retry in lockstep
+fn backoff(base: u64, attempt: u32) -> Duration {
+    let max = base.saturating_mul(1 << attempt.min(16));
+    Duration::from_millis(rand::thread_rng().gen_range(0..=max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::http::HttpClient;
+    use crate::fetcher::mock;
+    use async_trait::async_trait;
+    use axum::http::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Flaky fails with server errors for the first calls
+    struct Flaky {
+        url: String,
+        http: HttpClient,
+        fails: usize,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Exchange for Flaky {
+        async fn get_pairs(
+            &self,
+            _symbols: Vec<String>,
+        ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+            let calls = self.calls.fetch_add(1, Ordering::SeqCst);
+            let path = if calls < self.fails { "fail" } else { "ok" };
+            let url = format!("{}/{}", self.url, path);
+            self.http.send(self.http.get(&url)).await?;
+            Ok(Vec::new())
+        }
+    }
+
+    async fn flaky(fails: usize) -> Flaky {
+        let url = mock::serve(vec![
+            mock::route("/fail", StatusCode::INTERNAL_SERVER_ERROR, ""),
+            mock::route("/ok", StatusCode::OK, ""),
+        ])
+        .await;
+        Flaky {
+            url: url,
+            http: HttpClient::default(),
+            fails: fails,
+            calls: AtomicUsize::new(0),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let cfg = ResilienceConfig {
+            retries: 2,
+            backoff: 1,
+            ..Default::default()
+        };
+        let s_state = SharedState::default();
+        let exchange = flaky(2).await;
+        let result = get_pairs(&cfg, &s_state, "tiex", &exchange, Vec::new()).await;
+        assert!(result.is_ok());
+        assert_eq!(exchange.calls.load(Ordering::SeqCst), 3);
+        let s_state = s_state.lock().unwrap();
+        assert_eq!(s_state.exchange_health["tiex"].state, BreakerState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_breaker() {
+        let cfg = ResilienceConfig {
+            retries: 0,
+            failure_threshold: 2,
+            ..Default::default()
+        };
+        let s_state = SharedState::default();
+        let exchange = flaky(usize::MAX).await;
+        for _ in 0..3 {
+            let result = get_pairs(&cfg, &s_state, "tiex", &exchange, Vec::new()).await;
+            assert!(result.is_err());
+        }
+        // the third call is rejected without reaching the exchange
+        assert_eq!(exchange.calls.load(Ordering::SeqCst), 2);
+        s_state
+            .lock()
+            .unwrap()
+            .exchange_health
+            .get_mut("tiex")
+            .unwrap()
+            .open_until = 0;
+        // one probe after cooling down, which fails and opens the breaker again
+        assert!(get_pairs(&cfg, &s_state, "tiex", &exchange, Vec::new())
+            .await
+            .is_err());
+        assert_eq!(exchange.calls.load(Ordering::SeqCst), 3);
+        let s_state = s_state.lock().unwrap();
+        assert_eq!(s_state.exchange_health["tiex"].state, BreakerState::Open);
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limit = RateLimit {
+            rate: 2.0,
+            burst: 4.0,
+        };
+        let mut health = ExchangeHealth::new(limit, 0);
+        assert_eq!(health.take(limit, 3.0, 0), 0);
+        assert_eq!(health.take(limit, 3.0, 0), 1000);
+        assert_eq!(health.take(limit, 3.0, 1000), 0);
+        // a cost above the burst is capped, otherwise it would never be served
+        assert_eq!(health.take(limit, 10.0, 3000), 0);
+    }
+}
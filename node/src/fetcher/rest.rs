@@ -0,0 +1,309 @@
+use super::http::HttpClient;
+use super::stream;
+use super::{Exchange, PairInfo, VolumeUnit};
+use crate::flags::{TimestampFormat, VenueConfig, VenueMode};
+use crate::processor::utils;
+use async_trait::async_trait;
+use chrono::DateTime;
+use futures::future;
+use serde_json::Value;
+use std::error::Error;
+use strum_macros::Display;
+
+#[derive(Debug, Display)]
+pub enum RestError {
+    MissingTickers,
+    MissingPrice,
+    MissingVolume,
+    MissingTimestamp,
+}
+impl std::error::Error for RestError {}
+
+// Step is one hop of a selector like `$.result.*.c[0]`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Key(String),
+    Index(usize),
+    Any, //the first child of an object or array, for keys unknown ahead like pair ids of kraken
+}
+
+pub fn parse_selector(text: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::<Step>::new();
+    // `$` is optional, e.g. `result.last` equals to `$.result.last`
+    let path = text.trim().strip_prefix('$').unwrap_or(text.trim());
+    let path = match path.starts_with(|c| c == '.' || c == '[') || path.is_empty() {
+        true => path.to_string(),
+        false => format!(".{}", path),
+    };
+    let mut rest = path.as_str();
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix('[') {
+            let end = tail
+                .find(']')
+                .ok_or(format!("unclosed `[` in `{}`", text))?;
+            let index = &tail[..end];
+            steps.push(match index {
+                "*" => Step::Any,
+                _ => Step::Index(
+                    index
+                        .parse::<usize>()
+                        .map_err(|_| format!("invalid index `{}` in `{}`", index, text))?,
+                ),
+            });
+            rest = &tail[end + 1..];
+        } else if let Some(tail) = rest.strip_prefix('.') {
+            let end = tail.find(|c| c == '.' || c == '[').unwrap_or(tail.len());
+            let key = &tail[..end];
+            steps.push(match key {
+                "" => return Err(format!("empty key in `{}`", text)),
+                "*" => Step::Any,
+                _ => Step::Key(key.into()),
+            });
+            rest = &tail[end..];
+        } else {
+            return Err(format!("unexpected `{}` in `{}`", rest, text));
+        }
+    }
+    Ok(steps)
+}
+
+pub fn select<'a>(value: &'a Value, steps: &[Step]) -> Option<&'a Value> {
+    let mut value = value;
+    for step in steps {
+        value = match (step, value) {
+            (Step::Key(key), Value::Object(map)) => map.get(key)?,
+            (Step::Index(index), Value::Array(items)) => items.get(*index)?,
+            (Step::Any, Value::Object(map)) => map.values().next()?,
+            (Step::Any, Value::Array(items)) => items.first()?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+struct Selectors {
+    tickers: Vec<Step>,
+    symbol: Option<Vec<Step>>,
+    price: Vec<Step>,
+    volume: Vec<Step>,
+    bid: Option<Vec<Step>>,
+    ask: Option<Vec<Step>>,
+    timestamp: Option<Vec<Step>>,
+}
+
+// RestExchange reads tickers from a JSON endpoint described in config, see `venues` in node.yaml
+pub struct RestExchange {
+    name: String,
+    venue: VenueConfig,
+    selectors: Selectors,
+    http: HttpClient,
+}
+
+pub fn new(
+    name: &str,
+    venue: &VenueConfig,
+    http: HttpClient,
+) -> Result<RestExchange, Box<dyn Error + Send + Sync>> {
+    if venue.mode == VenueMode::PerSymbol && !venue.url.contains("{symbol}") {
+        return Err(format!("url of venue {} has no {{symbol}}", name).into());
+    }
+    let optional = |selector: &Option<String>| selector.as_deref().map(parse_selector).transpose();
+    Ok(RestExchange {
+        name: name.into(),
+        venue: venue.clone(),
+        selectors: Selectors {
+            tickers: parse_selector(venue.tickers.as_deref().unwrap_or("$"))?,
+            symbol: optional(&venue.symbol)?,
+            price: parse_selector(&venue.price)?,
+            volume: parse_selector(&venue.volume)?,
+            bid: optional(&venue.bid)?,
+            ask: optional(&venue.ask)?,
+            timestamp: optional(&venue.timestamp)?,
+        },
+        http: http,
+    })
+}
+
+#[async_trait]
+impl Exchange for RestExchange {
+    async fn get_pairs(
+        &self,
+        symbols: Vec<String>,
+    ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+        match self.venue.mode {
+            VenueMode::Bulk => self.get_bulk(&symbols).await,
+            VenueMode::PerSymbol => {
+                let requests = symbols.iter().map(|symbol| self.get_pair(symbol));
+                future::try_join_all(requests).await
+            }
+        }
+    }
+
+    fn requests(&self, symbols: &Vec<String>) -> usize {
+        match self.venue.mode {
+            VenueMode::Bulk => 1,
+            VenueMode::PerSymbol => symbols.len(),
+        }
+    }
+}
+
+impl RestExchange {
+    // tickers of all symbols in one response, as an array or an object keyed by symbols
+    async fn get_bulk(
+        &self,
+        symbols: &Vec<String>,
+    ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+        let response = self.http.send(self.http.get(&self.venue.url)).await?;
+        let server_time = super::server_time(&response);
+        let body: Value = response.json().await?;
+        let tickers = select(&body, &self.selectors.tickers).ok_or(RestError::MissingTickers)?;
+        let entries: Vec<(Option<&String>, &Value)> = match tickers {
+            Value::Array(items) => items.iter().map(|item| (None, item)).collect(),
+            Value::Object(map) => map.iter().map(|(key, item)| (Some(key), item)).collect(),
+            _ => return Err(RestError::MissingTickers.into()),
+        };
+        let mut result = Vec::<PairInfo>::new();
+        for (key, ticker) in entries {
+            let symbol = match &self.selectors.symbol {
+                Some(selector) => select(ticker, selector).and_then(|symbol| symbol.as_str()),
+                None => key.map(|key| key.as_str()),
+            };
+            if let Some(symbol) = symbol.filter(|symbol| symbols.iter().any(|s| s == symbol)) {
+                result.push(self.parse(symbol, ticker, server_time)?);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn get_pair(&self, symbol: &str) -> Result<PairInfo, Box<dyn Error + Send + Sync>> {
+        let request_url = self.venue.url.replace("{symbol}", symbol);
+        let response = self.http.send(self.http.get(&request_url)).await?;
+        let server_time = super::server_time(&response);
+        let body: Value = response.json().await?;
+        let ticker = select(&body, &self.selectors.tickers).ok_or(RestError::MissingTickers)?;
+        self.parse(symbol, ticker, server_time)
+    }
+
+    fn parse(
+        &self,
+        symbol: &str,
+        ticker: &Value,
+        server_time: Option<u64>,
+    ) -> Result<PairInfo, Box<dyn Error + Send + Sync>> {
+        let field = |steps: &Vec<Step>| select(ticker, steps).and_then(stream::value_f64);
+        let price = field(&self.selectors.price).ok_or(RestError::MissingPrice)?;
+        let volume = field(&self.selectors.volume).ok_or(RestError::MissingVolume)?;
+        let timestamp = match &self.selectors.timestamp {
+            Some(selector) => {
+                let value = select(ticker, selector).ok_or(RestError::MissingTimestamp)?;
+                parse_timestamp(value, &self.venue.timestamp_format)
+                    .ok_or(RestError::MissingTimestamp)?
+            }
+            None => server_time.unwrap_or_else(utils::timestamp),
+        };
+        Ok(PairInfo {
+            symbol: symbol.into(),
+            price: price,
+            volume: volume,
+            timestamp: timestamp,
+            exchange: self.name.clone(),
+            depth: None,
+            volume_unit: VolumeUnit {
+                side: self.venue.volume_unit.clone(),
+                asset: None,
+            },
+            bid: self.selectors.bid.as_ref().and_then(field),
+            ask: self.selectors.ask.as_ref().and_then(field),
+        })
+    }
+}
+
+// parse the timestamp of a ticker into ms
+fn parse_timestamp(value: &Value, format: &TimestampFormat) -> Option<u64> {
+    match format {
+        TimestampFormat::Ms => stream::value_f64(value).map(|ts| ts as u64),
+        TimestampFormat::S => stream::value_f64(value).map(|ts| (ts * 1000.0) as u64),
+        TimestampFormat::Rfc3339 => {
+            let time = DateTime::parse_from_rfc3339(value.as_str()?).ok()?;
+            Some(time.timestamp_millis() as u64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::mock;
+    use crate::fetcher::Side;
+    use axum::http::StatusCode;
+
+    fn venue(mode: VenueMode, url: &str) -> VenueConfig {
+        VenueConfig {
+            url: url.into(),
+            mode: mode,
+            tickers: None,
+            symbol: None,
+            price: "$.last".into(),
+            volume: "$.vol".into(),
+            bid: None,
+            ask: None,
+            timestamp: None,
+            timestamp_format: TimestampFormat::Ms,
+            volume_unit: Side::Base,
+        }
+    }
+
+    #[test]
+    fn test_selector() {
+        let value: Value =
+            serde_json::from_str(r#"{"result":{"XXBTZUSD":{"c":["23000.1","0.01"]}}}"#).unwrap();
+        let steps = parse_selector("$.result.*.c[0]").unwrap();
+        assert_eq!(select(&value, &steps), Some(&Value::from("23000.1")));
+        assert_eq!(select(&value, &parse_selector("result.x").unwrap()), None);
+        assert!(parse_selector("$.c[x]").is_err());
+        assert!(parse_selector("$..c").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let url = mock::serve(vec![
+            mock::route(
+                "/tickers",
+                StatusCode::OK,
+                r#"{"data":[{"s":"BTC_USDT","last":"23001.5","vol":"12.5","bid":"23001","t":1660000000},{"s":"ETH_USDT","last":"1700","vol":"99","t":1660000000}]}"#,
+            ),
+            mock::route(
+                "/ticker?pair=BTCUSD",
+                StatusCode::OK,
+                r#"{"result":{"XXBTZUSD":{"last":23002.5,"vol":7.5}}}"#,
+            ),
+        ])
+        .await;
+        let mut bulk = venue(VenueMode::Bulk, &format!("{}/tickers", url));
+        bulk.tickers = Some("$.data".into());
+        bulk.symbol = Some("$.s".into());
+        bulk.timestamp = Some("$.t".into());
+        bulk.timestamp_format = TimestampFormat::S;
+        bulk.bid = Some("$.bid".into());
+        bulk.ask = Some("$.ask".into());
+        let gate = new("gate", &bulk, HttpClient::default()).unwrap();
+        let result = gate.get_pairs(vec!["BTC_USDT".into()]).await.unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].price, 23001.5);
+        assert_eq!(result[0].timestamp, 1660000000000);
+        assert_eq!(result[0].exchange, "gate");
+        assert_eq!(result[0].bid, Some(23001.0));
+        assert_eq!(result[0].ask, None);
+
+        let mut per_symbol = venue(
+            VenueMode::PerSymbol,
+            &format!("{}/ticker?pair={{symbol}}", url),
+        );
+        per_symbol.tickers = Some("$.result.*".into());
+        let kraken = new("kraken", &per_symbol, HttpClient::default()).unwrap();
+        let result = kraken.get_pairs(vec!["BTCUSD".into()]).await.unwrap();
+        assert_eq!(result[0].symbol, "BTCUSD");
+        assert_eq!(result[0].volume, 7.5);
+        assert!(kraken.get_pairs(vec!["ETHUSD".into()]).await.is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use super::PairInfo;
+use crate::processor::utils;
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use strum_macros::Display;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+const RECONNECT_DELAY: u64 = 3000;
+const IDLE_TIMEOUT: u64 = 60000;
+
+#[derive(Debug, Display)]
+pub enum StreamError {
+    Idle(u64),
+    Closed,
+}
+impl std::error::Error for StreamError {}
+
+// StreamSpec describes how to subscribe the ticker channel of one exchange through websocket
+#[async_trait]
+pub trait StreamSpec: Send + Sync + 'static {
+    fn name(&self) -> &str;
+    // url of the websocket to connect to
+    async fn endpoint(&self, symbols: &Vec<String>)
+        -> Result<String, Box<dyn Error + Send + Sync>>;
+    // messages sent right after connected, to subscribe tickers of symbols
+    fn subscribe_messages(&self, symbols: &Vec<String>) -> Vec<String>;
+    // tickers parsed from a message received
+    fn parse(&self, msg: &str) -> Vec<PairInfo>;
+    // message sent periodically to keep the connection alive, if required by exchange
+    fn ping_message(&self) -> Option<String> {
+        None
+    }
+    fn ping_interval(&self) -> u64 {
+        15000
+    }
+    // the connection is dropped if nothing is received for so long, in ms
+    fn idle_timeout(&self) -> u64 {
+        IDLE_TIMEOUT
+    }
+}
+
+// TickerCache keeps the latest snapshot of each symbol received from websocket
+#[derive(Clone)]
+pub struct TickerCache {
+    max_age: u64,                                           //in ms
+    tickers: Arc<Mutex<BTreeMap<String, (PairInfo, u64)>>>, //symbol -> (ticker, received time)
+}
+
+impl TickerCache {
+    pub fn new(max_age: u64) -> TickerCache {
+        TickerCache {
+            max_age: max_age,
+            tickers: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    pub fn update(&self, pair: PairInfo) {
+        let now = utils::timestamp();
+        self.tickers
+            .lock()
+            .unwrap()
+            .insert(pair.symbol.clone(), (pair, now));
+    }
+
+    // get snapshots of all the symbols, None if any of them is missing or stale
+    pub fn get_all(&self, symbols: &Vec<String>) -> Option<Vec<PairInfo>> {
+        let now = utils::timestamp();
+        let tickers = self.tickers.lock().unwrap();
+        let mut result = Vec::<PairInfo>::new();
+        for symbol in symbols {
+            match tickers.get(symbol) {
+                Some((pair, received)) if *received + self.max_age >= now => {
+                    result.push(pair.clone());
+                }
+                Some(_) => {
+                    debug!("stale ticker of {}", symbol);
+                    return None;
+                }
+                None => {
+                    debug!("no ticker of {}", symbol);
+                    return None;
+                }
+            }
+        }
+        Some(result)
+    }
+}
+
+// StreamHub shares one stream of each exchange among all feeds, feeds claim their symbols first,
+// then each stream is started once with the union of symbols claimed
+#[derive(Clone)]
+pub struct StreamHub {
+    max_age: u64,                                        //in ms
+    streams: Arc<Mutex<BTreeMap<String, SharedStream>>>, //exchange -> stream
+}
+
+struct SharedStream {
+    cache: TickerCache,
+    symbols: Vec<String>,
+    started: bool,
+}
+
+impl StreamHub {
+    pub fn new(max_age: u64) -> StreamHub {
+        StreamHub {
+            max_age: max_age,
+            streams: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    // cache of an exchange, the same one for all feeds
+    pub fn cache(&self, exchange: &str) -> TickerCache {
+        self.streams
+            .lock()
+            .unwrap()
+            .entry(exchange.into())
+            .or_insert_with(|| SharedStream {
+                cache: TickerCache::new(self.max_age),
+                symbols: Vec::new(),
+                started: false,
+            })
+            .cache
+            .clone()
+    }
+
+    // claim adds symbols a feed reads from the stream of an exchange
+    pub fn claim(&self, exchange: &str, symbols: &Vec<String>) {
+        self.cache(exchange);
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.get_mut(exchange).unwrap();
+        if stream.started {
+            warn!(
+                "stream of {} already started, {:?} not subscribed",
+                exchange, symbols
+            );
+            return;
+        }
+        for symbol in symbols {
+            if !stream.symbols.contains(symbol) {
+                stream.symbols.push(symbol.clone());
+            }
+        }
+    }
+
+    // start subscribes symbols claimed of the exchange of spec, once
+    pub fn start(&self, spec: Box<dyn StreamSpec>) {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = match streams.get_mut(spec.name()) {
+            Some(stream) if !stream.started && !stream.symbols.is_empty() => stream,
+            _ => return,
+        };
+        stream.started = true;
+        spawn(spec, stream.symbols.clone(), stream.cache.clone());
+    }
+}
+
+// subscribe tickers in background, and reconnect if anything goes wrong
+pub fn spawn(spec: Box<dyn StreamSpec>, symbols: Vec<String>, cache: TickerCache) {
+    tokio::task::spawn(async move {
+        loop {
+            if let Err(err) = subscribe(spec.as_ref(), &symbols, &cache).await {
+                warn!("stream of {} error: {}", spec.name(), err);
+            }
+            tokio::time::sleep(Duration::from_millis(RECONNECT_DELAY)).await;
+        }
+    });
+}
+
+async fn subscribe<S: StreamSpec + ?Sized>(
+    spec: &S,
+    symbols: &Vec<String>,
+    cache: &TickerCache,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let url = spec.endpoint(symbols).await?;
+    let (ws_stream, _) = connect_async(url.as_str()).await?;
+    info!("stream of {} connected", spec.name());
+    let (mut write, mut read) = ws_stream.split();
+    for msg in spec.subscribe_messages(symbols) {
+        write.send(Message::Text(msg)).await?;
+    }
+    let mut ping = tokio::time::interval(Duration::from_millis(spec.ping_interval()));
+    // deadline of the connection, only pushed back by frames received, not by pings sent
+    let idle_timeout = Duration::from_millis(spec.idle_timeout());
+    let idle = tokio::time::sleep(idle_timeout);
+    tokio::pin!(idle);
+    loop {
+        tokio::select! {
+            _ = ping.tick() => {
+                if let Some(msg) = spec.ping_message() {
+                    write.send(Message::Text(msg)).await?;
+                }
+            }
+            _ = &mut idle => return Err(Box::new(StreamError::Idle(spec.idle_timeout()))),
+            msg = read.next() => {
+                let msg = match msg {
+                    None => return Err(Box::new(StreamError::Closed)),
+                    Some(msg) => msg?,
+                };
+                idle.as_mut().reset(tokio::time::Instant::now() + idle_timeout);
+                match msg {
+                    Message::Text(txt) => {
+                        for pair in spec.parse(&txt) {
+                            cache.update(pair);
+                        }
+                    }
+                    Message::Close(_) => return Err(Box::new(StreamError::Closed)),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// read a number from json, which may be represented as a string
+pub fn value_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::VolumeUnit;
+    #[test]
+    fn test_cache() {
+        let cache = TickerCache::new(1000);
+        cache.update(PairInfo {
+            symbol: "a".into(),
+            price: 8.0,
+            volume: 1.0,
+            timestamp: 0,
+            exchange: "tiex".into(),
+            depth: None,
+            volume_unit: VolumeUnit::base(None),
+            bid: None,
+            ask: None,
+        });
+        assert_eq!(cache.get_all(&vec!["a".into()]).unwrap().len(), 1);
+        assert!(cache.get_all(&vec!["a".into(), "b".into()]).is_none());
+        cache.tickers.lock().unwrap().get_mut("a").unwrap().1 -= 2000;
+        assert!(cache.get_all(&vec!["a".into()]).is_none());
+    }
+
+    #[test]
+    fn test_hub() {
+        let hub = StreamHub::new(1000);
+        hub.claim("tiex", &vec!["a".into(), "b".into()]);
+        hub.claim("tiex", &vec!["b".into(), "c".into()]);
+        hub.cache("tiex").update(PairInfo {
+            symbol: "a".into(),
+            price: 8.0,
+            volume: 1.0,
+            timestamp: 0,
+            exchange: "tiex".into(),
+            depth: None,
+            volume_unit: VolumeUnit::base(None),
+            bid: None,
+            ask: None,
+        });
+        // feeds share the cache of an exchange
+        assert!(hub.cache("tiex").get_all(&vec!["a".into()]).is_some());
+        let streams = hub.streams.lock().unwrap();
+        assert_eq!(streams["tiex"].symbols, vec!["a", "b", "c"]);
+        assert!(!streams["tiex"].started);
+    }
+
+    // a spec which pings often, against a server which goes silent after the first ticker
+    struct SilentSpec {
+        url: String,
+    }
+
+    #[async_trait]
+    impl StreamSpec for SilentSpec {
+        fn name(&self) -> &str {
+            "tiex"
+        }
+        async fn endpoint(
+            &self,
+            _symbols: &Vec<String>,
+        ) -> Result<String, Box<dyn Error + Send + Sync>> {
+            Ok(self.url.clone())
+        }
+        fn subscribe_messages(&self, _symbols: &Vec<String>) -> Vec<String> {
+            vec![]
+        }
+        fn parse(&self, msg: &str) -> Vec<PairInfo> {
+            vec![PairInfo {
+                symbol: msg.into(),
+                price: 8.0,
+                volume: 1.0,
+                timestamp: 0,
+                exchange: "tiex".into(),
+                depth: None,
+                volume_unit: VolumeUnit::base(None),
+                bid: None,
+                ask: None,
+            }]
+        }
+        fn ping_message(&self) -> Option<String> {
+            Some("ping".into())
+        }
+        fn ping_interval(&self) -> u64 {
+            50
+        }
+        fn idle_timeout(&self) -> u64 {
+            300
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+            ws.send(Message::Text("a".into())).await.unwrap();
+            // pings are read but never answered
+            while let Some(Ok(_)) = ws.next().await {}
+        });
+        let spec = SilentSpec {
+            url: format!("ws://{}", addr),
+        };
+        let cache = TickerCache::new(1000);
+        let result = tokio::time::timeout(
+            Duration::from_millis(3000),
+            subscribe(&spec, &vec!["a".into()], &cache),
+        )
+        .await
+        .expect("pings must not keep an idle stream alive");
+        assert_eq!(result.unwrap_err().to_string(), "Idle");
+        assert!(cache.get_all(&vec!["a".into()]).is_some());
+    }
+}
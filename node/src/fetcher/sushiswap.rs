@@ -1,13 +1,14 @@
-use super::expression;
+use super::http::HttpClient;
+use super::pool;
 use super::Exchange;
 use super::PairInfo;
-use crate::processor::utils;
+use crate::chains::dex::{self, ChainReader, PoolState};
 use async_trait::async_trait;
-use reqwest::ClientBuilder;
+use ethers::prelude::Address;
+use futures::future;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 use std::error::Error;
-use std::time::Duration;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -19,6 +20,28 @@ pub struct Response {
 #[serde(rename_all = "camelCase")]
 pub struct Data {
     pub pairs: Vec<Pair>,
+    pub pair_hour_datas: Vec<PairHourData>,
+    #[serde(rename = "_meta")]
+    pub meta: Meta,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub block: MetaBlock,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetaBlock {
+    pub timestamp: u64, //in seconds, of the latest block indexed by the subgraph
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairHourData {
+    pub hour_start_unix: u64,
+    pub hourly_volume_token0: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -31,6 +54,8 @@ pub struct Pair {
     pub token1price: String,
     pub volume_token0: String,
     pub volume_token1: String,
+    pub reserve0: String,
+    pub reserve1: String,
     pub token0: Token0,
     pub token1: Token1,
 }
@@ -47,10 +72,28 @@ pub struct Token1 {
     pub symbol: String,
 }
 
-#[derive(Default, Clone)]
-pub struct Sushiswap {}
+#[derive(Clone)]
+pub struct Sushiswap {
+    pub subgraph_url: String,
+    pub http: HttpClient,
+    pub chain: Option<ChainReader>, //read pools from chain state if specified, otherwise from subgraph
+    pub coin: String,               //base token of pools which do not specify one
+}
+
+impl Default for Sushiswap {
+    fn default() -> Self {
+        Sushiswap {
+            subgraph_url: SUBGRAPH_URL.into(),
+            http: HttpClient::default(),
+            chain: None,
+            coin: String::new(),
+        }
+    }
+}
 
 pub const NAME: &str = "sushiswap";
+pub const SUBGRAPH_URL: &str =
+    "https://api.thegraph.com/subgraphs/name/zippoxer/sushiswap-subgraph-fork";
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct QueryRequest {
@@ -63,65 +106,144 @@ impl Exchange for Sushiswap {
         &self,
         symbols: Vec<String>,
     ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
-        let mut result = Vec::<PairInfo>::new();
-        for symbol in expression::expand_symbols(&symbols) {
-            let request_url =
-                "https://api.thegraph.com/subgraphs/name/zippoxer/sushiswap-subgraph-fork";
-            //println!("{}", request_url);
-            let timeout = Duration::new(5, 0);
-            let client = ClientBuilder::new().timeout(timeout).build()?;
-            let fmt_str = r#"
-            query {
-                pairs(where:{id_in:["{pair_id}"]})  {
-                  id
-                  token0Price
-                  token1Price
-                  volumeToken0
-                  volumeToken1
-                  token0 { symbol }
-                  token1 { symbol }
-                }
-              }
-        "#;
-            let query_params = fmt_str.to_string().replace("{pair_id}", &symbol);
-            let query: QueryRequest = QueryRequest {
-                query: query_params,
-            };
-            let content = serde_json::to_string(&query).unwrap();
-            //println!("{}", content);
-            let response = client.post(request_url).body(content).send().await?;
-            //println!("{}", response.text().await?);
-            let response_json: Response = response.json().await?;
-            let adjust_weight = 0.0063; //TODO: later, we should fetch 24hours volume
-            if response_json.data.pairs.len() > 0 {
-                result.push(PairInfo {
-                    symbol: symbol.clone(),
-                    price: response_json.data.pairs[0].token1price.parse::<f64>()?,
-                    volume: response_json.data.pairs[0].volume_token0.parse::<f64>()?
-                        * adjust_weight,
-                    timestamp: utils::timestamp() as u64, //TODO timestamp
-                    exchange: NAME.into(),
-                });
-            }
+        if let Some(chain) = &self.chain {
+            return get_pairs_onchain(chain, &self.coin, symbols).await;
+        }
+        // pools are queried one by one, so symbols are fetched concurrently
+        let requests = symbols.iter().map(|symbol| self.get_pair(symbol));
+        let result = future::try_join_all(requests).await?;
+        Ok(result.into_iter().flatten().collect())
+    }
+
+    fn requests(&self, symbols: &Vec<String>) -> usize {
+        symbols.len()
+    }
+
+    async fn validate(&self, symbols: &Vec<String>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(chain) = &self.chain {
+            return pool::validate_onchain(chain, &self.coin, symbols).await;
         }
-        let result = expression::reduce_symbols(&symbols, &result);
-        return Ok(result);
+        self.get_pairs(symbols.clone()).await?;
+        Ok(())
+    }
+}
+
+impl Sushiswap {
+    // query the pool from subgraph, or None if it is not indexed
+    async fn get_pair(
+        &self,
+        symbol: &str,
+    ) -> Result<Option<PairInfo>, Box<dyn Error + Send + Sync>> {
+        let fmt_str = r#"
+        query {
+            pairs(where:{id_in:["{pair_id}"]})  {
+              id
+              token0Price
+              token1Price
+              volumeToken0
+              volumeToken1
+              reserve0
+              reserve1
+              token0 { symbol }
+              token1 { symbol }
+            }
+            pairHourDatas(first:25, orderBy:hourStartUnix, orderDirection:desc, where:{pair:"{pair_id}"}) {
+              hourStartUnix
+              hourlyVolumeToken0
+            }
+            _meta { block { timestamp } }
+          }
+    "#;
+        let spec = pool::parse_spec(symbol);
+        let query_params = fmt_str.to_string().replace("{pair_id}", &spec.address);
+        let query: QueryRequest = QueryRequest {
+            query: query_params,
+        };
+        let content = serde_json::to_string(&query).unwrap();
+        let request = self.http.post(&self.subgraph_url).body(content);
+        let response_json: Response = self.http.send(request).await?.json().await?;
+        let data = &response_json.data;
+        let pair = match data.pairs.first() {
+            Some(pair) => pair,
+            None => return Ok(None),
+        };
+        // token1Price is the price of token0 quoted by token1
+        let state = PoolState {
+            tokens: [pair.token0.symbol.clone(), pair.token1.symbol.clone()],
+            price: pair.token1price.parse::<f64>()?,
+            volume: pool::volume_24h(
+                data.pair_hour_datas
+                    .iter()
+                    .map(|hour| (hour.hour_start_unix, hour.hourly_volume_token0.as_str())),
+                data.meta.block.timestamp,
+            )?,
+            depths: [pair.reserve0.parse::<f64>()?, pair.reserve1.parse::<f64>()?],
+        };
+        let timestamp = data.meta.block.timestamp * 1000;
+        Ok(Some(pool::pair_info(
+            symbol, &self.coin, &state, NAME, timestamp,
+        )?))
+    }
+}
+
+// read price and volume of each pool from chain state
+async fn get_pairs_onchain(
+    chain: &ChainReader,
+    coin: &str,
+    symbols: Vec<String>,
+) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+    let provider = chain.provider()?;
+    let (block, timestamp) = chain.block_info(&provider).await?;
+    let mut result = Vec::<PairInfo>::new();
+    for symbol in symbols {
+        let address = pool::parse_spec(&symbol).address.parse::<Address>()?;
+        let state = dex::read_v2_pair(chain, provider.clone(), address, block).await?;
+        result.push(pool::pair_info(&symbol, coin, &state, NAME, timestamp)?);
     }
+    return Ok(result);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fetcher::expression;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
     #[tokio::test]
     async fn test_fetch() {
-        let uni = Sushiswap::default();
-        let result = uni
-            .get_pairs(vec![
-                "0xceff51756c56ceffca006cd410b03ffc46dd3a58 div 0x397ff1542f962076d0bfe58ea045ffa2d347aca0".into(),
-            ])
-            .await;
-        let result = result.unwrap();
+        let url = mock::serve(vec![
+            mock::route(
+                "0xceff51756c56ceffca006cd410b03ffc46dd3a58",
+                StatusCode::OK,
+                include_str!(
+                    "../../fixtures/sushiswap/0xceff51756c56ceffca006cd410b03ffc46dd3a58.json"
+                ),
+            ),
+            mock::route(
+                "0x397ff1542f962076d0bfe58ea045ffa2d347aca0",
+                StatusCode::OK,
+                include_str!(
+                    "../../fixtures/sushiswap/0x397ff1542f962076d0bfe58ea045ffa2d347aca0.json"
+                ),
+            ),
+        ])
+        .await;
+        let uni = Sushiswap {
+            subgraph_url: url,
+            coin: "BTC".into(),
+            ..Default::default()
+        };
+        let symbols = vec![
+            "0xceff51756c56ceffca006cd410b03ffc46dd3a58 div 0x397ff1542f962076d0bfe58ea045ffa2d347aca0:USDC".into(),
+        ];
+        let symbols = expression::parse_symbols(&symbols).unwrap();
+        let legs = uni
+            .get_pairs(expression::expand_symbols(&symbols))
+            .await
+            .unwrap();
+        let (result, _) = expression::reduce_symbols(&symbols, &legs);
         println!("{:?}", result);
         assert_eq!(result.len(), 1);
+        assert!((result[0].price - 13.5088 / 0.0005873).abs() < 1e-6);
     }
 }
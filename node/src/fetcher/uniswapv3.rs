@@ -1,14 +1,14 @@
-use crate::processor::utils;
-
-use super::expression;
+use super::http::HttpClient;
+use super::pool;
 use super::Exchange;
 use super::PairInfo;
+use crate::chains::dex::{self, ChainReader, PoolState};
 use async_trait::async_trait;
-use reqwest::ClientBuilder;
+use ethers::prelude::Address;
+use futures::future;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 use std::error::Error;
-use std::time::Duration;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -20,6 +20,20 @@ pub struct Pair {
 #[serde(rename_all = "camelCase")]
 pub struct Data {
     pub pool: Pool,
+    #[serde(rename = "_meta")]
+    pub meta: Meta,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub block: MetaBlock,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetaBlock {
+    pub timestamp: u64, //in seconds, of the latest block indexed by the subgraph
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -32,8 +46,18 @@ pub struct Pool {
     pub token1price: String,
     pub volume_token0: String,
     pub volume_token1: String,
+    pub total_value_locked_token0: String,
+    pub total_value_locked_token1: String,
     pub token0: Token0,
     pub token1: Token1,
+    pub pool_hour_data: Vec<PoolHourData>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolHourData {
+    pub period_start_unix: u64,
+    pub volume_token0: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -53,10 +77,27 @@ struct QueryRequest {
     pub query: String,
 }
 
-#[derive(Default, Clone)]
-pub struct UniswapV3 {}
+#[derive(Clone)]
+pub struct UniswapV3 {
+    pub subgraph_url: String,
+    pub http: HttpClient,
+    pub chain: Option<ChainReader>, //read pools from chain state if specified, otherwise from subgraph
+    pub coin: String,               //base token of pools which do not specify one
+}
+
+impl Default for UniswapV3 {
+    fn default() -> Self {
+        UniswapV3 {
+            subgraph_url: SUBGRAPH_URL.into(),
+            http: HttpClient::default(),
+            chain: None,
+            coin: String::new(),
+        }
+    }
+}
 
 pub const NAME: &str = "uniswapv3";
+pub const SUBGRAPH_URL: &str = "https://api.thegraph.com/subgraphs/name/uniswap/uniswap-v3";
 
 #[async_trait]
 impl Exchange for UniswapV3 {
@@ -64,61 +105,148 @@ impl Exchange for UniswapV3 {
         &self,
         symbols: Vec<String>,
     ) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
-        let mut result = Vec::<PairInfo>::new();
-        for symbol in expression::expand_symbols(&symbols) {
-            let request_url = "https://api.thegraph.com/subgraphs/name/uniswap/uniswap-v3";
-            //println!("{}", request_url);
-            let timeout = Duration::new(5, 0);
-            let client = ClientBuilder::new().timeout(timeout).build()?;
-            let fmt_str = r#"
-        {
-            pool(id: "{pool_id}") {
-              id
-              token0Price
-              token1Price
-              volumeToken0
-              volumeToken1
-              token0 { symbol }
-              token1 { symbol }
-            }
+        if let Some(chain) = &self.chain {
+            return get_pairs_onchain(chain, &self.coin, symbols).await;
+        }
+        // pools are queried one by one, so symbols are fetched concurrently
+        let requests = symbols.iter().map(|symbol| self.get_pair(symbol));
+        future::try_join_all(requests).await
+    }
+
+    fn requests(&self, symbols: &Vec<String>) -> usize {
+        symbols.len()
+    }
+
+    async fn validate(&self, symbols: &Vec<String>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(chain) = &self.chain {
+            return pool::validate_onchain(chain, &self.coin, symbols).await;
+        }
+        self.get_pairs(symbols.clone()).await?;
+        Ok(())
+    }
+}
+
+impl UniswapV3 {
+    async fn get_pair(&self, symbol: &str) -> Result<PairInfo, Box<dyn Error + Send + Sync>> {
+        let fmt_str = r#"
+    {
+        pool(id: "{pool_id}") {
+          id
+          token0Price
+          token1Price
+          volumeToken0
+          volumeToken1
+          totalValueLockedToken0
+          totalValueLockedToken1
+          token0 { symbol }
+          token1 { symbol }
+          poolHourData(first:25, orderBy:periodStartUnix, orderDirection:desc) {
+            periodStartUnix
+            volumeToken0
           }
-        "#;
-            let query_params = fmt_str.to_string().replace("{pool_id}", &symbol);
-            let query: QueryRequest = QueryRequest {
-                query: query_params,
-            };
-            let content = serde_json::to_string(&query).unwrap();
-            //println!("{}", content);
-            let response = client.post(request_url).body(content).send().await?;
-            //println!("{}", response.text().await?);
-            let pair: Pair = response.json().await?;
-            let adjust_weight = 0.0063; //TODO: later, we should fetch 24hours volume
-            result.push(PairInfo {
-                symbol: symbol.clone(),
-                price: pair.data.pool.token1price.parse::<f64>()?,
-                volume: pair.data.pool.volume_token0.parse::<f64>()? * adjust_weight,
-                timestamp: utils::timestamp() as u64, //TODO timestamp
-                exchange: NAME.into(),
-            });
         }
-        let result = expression::reduce_symbols(&symbols, &result);
-        return Ok(result);
+        _meta { block { timestamp } }
+      }
+    "#;
+        let spec = pool::parse_spec(symbol);
+        let query_params = fmt_str.to_string().replace("{pool_id}", &spec.address);
+        let query: QueryRequest = QueryRequest {
+            query: query_params,
+        };
+        let content = serde_json::to_string(&query).unwrap();
+        let request = self.http.post(&self.subgraph_url).body(content);
+        let pair: Pair = self.http.send(request).await?.json().await?;
+        let pool = &pair.data.pool;
+        // token1Price is the price of token0 quoted by token1
+        let state = PoolState {
+            tokens: [pool.token0.symbol.clone(), pool.token1.symbol.clone()],
+            price: pool.token1price.parse::<f64>()?,
+            volume: pool::volume_24h(
+                pool.pool_hour_data
+                    .iter()
+                    .map(|hour| (hour.period_start_unix, hour.volume_token0.as_str())),
+                pair.data.meta.block.timestamp,
+            )?,
+            depths: [
+                pool.total_value_locked_token0.parse::<f64>()?,
+                pool.total_value_locked_token1.parse::<f64>()?,
+            ],
+        };
+        let timestamp = pair.data.meta.block.timestamp * 1000;
+        Ok(pool::pair_info(
+            symbol, &self.coin, &state, NAME, timestamp,
+        )?)
+    }
+}
+
+// read price and volume of each pool from chain state
+async fn get_pairs_onchain(
+    chain: &ChainReader,
+    coin: &str,
+    symbols: Vec<String>,
+) -> Result<Vec<PairInfo>, Box<dyn Error + Send + Sync>> {
+    let provider = chain.provider()?;
+    let (block, timestamp) = chain.block_info(&provider).await?;
+    let mut result = Vec::<PairInfo>::new();
+    for symbol in symbols {
+        let address = pool::parse_spec(&symbol).address.parse::<Address>()?;
+        let state = dex::read_v3_pool(chain, provider.clone(), address, block).await?;
+        result.push(pool::pair_info(&symbol, coin, &state, NAME, timestamp)?);
     }
+    return Ok(result);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fetcher::expression;
+    use crate::fetcher::mock;
+    use axum::http::StatusCode;
     #[tokio::test]
     async fn test_fetch() {
-        let uni = UniswapV3::default();
-        let result = uni
-            .get_pairs(vec![
-                "0xcbcdf9626bc03e24f779434178a73a0b4bad62ed div 0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8".into(),
-            ])
-            .await;
-        let result = result.unwrap();
+        let url = mock::serve(vec![
+            mock::route(
+                "0xcbcdf9626bc03e24f779434178a73a0b4bad62ed",
+                StatusCode::OK,
+                include_str!(
+                    "../../fixtures/uniswapv3/0xcbcdf9626bc03e24f779434178a73a0b4bad62ed.json"
+                ),
+            ),
+            mock::route(
+                "0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8",
+                StatusCode::OK,
+                include_str!(
+                    "../../fixtures/uniswapv3/0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8.json"
+                ),
+            ),
+        ])
+        .await;
+        let uni = UniswapV3 {
+            subgraph_url: url,
+            coin: "BTC".into(),
+            ..Default::default()
+        };
+        // USDC is token0 and WETH is token1 of 0x8ad5...
+        let symbols = vec![
+            "0xcbcdf9626bc03e24f779434178a73a0b4bad62ed div 0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8:USDC".into(),
+            "0xcbcdf9626bc03e24f779434178a73a0b4bad62ed mul 0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8:WETH".into(),
+        ];
+        let symbols = expression::parse_symbols(&symbols).unwrap();
+        let legs = uni
+            .get_pairs(expression::expand_symbols(&symbols))
+            .await
+            .unwrap();
+        let (result, _) = expression::reduce_symbols(&symbols, &legs);
         println!("{:?}", result);
-        assert_eq!(result.len(), 1);
+        assert_eq!(result.len(), 2);
+        assert!((result[0].price - 13.5302 / 0.0005873).abs() < 1e-6);
+        assert_eq!(result[0].depth, Some(2345.6));
+        assert!((result[1].price - result[0].price).abs() < 1e-6);
+        // BTC is not in the pool of USDC and WETH
+        let err = uni
+            .validate(&vec!["0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8".into()])
+            .await
+            .unwrap_err();
+        assert!(err.is::<pool::PoolError>());
     }
 }
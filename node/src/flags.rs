@@ -1,26 +1,411 @@
+use crate::fetcher::{aggregator, expression, resilience, rest, Side};
 use serde_derive::Deserialize;
 use std::collections::BTreeMap;
 use std::vec::Vec;
 
+// DexMode decides where DEX fetchers read pools from
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DexMode {
+    Subgraph,
+    Onchain,
+}
+
+impl Default for DexMode {
+    fn default() -> Self {
+        DexMode::Subgraph
+    }
+}
+
+// Strategy decides how prices of pairs are aggregated into the price of a feed
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    VolumeWeightedMean,
+    VolumeWeightedMedian,
+    LiquidityWeighted,
+    TrimmedMean,
+    Twap,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::VolumeWeightedMean
+    }
+}
+
+// PriceSource decides which price of a pair is aggregated
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceSource {
+    Last,
+    Mid, //middle of the best bid and ask, the last price is used if they are unknown
+}
+
+impl Default for PriceSource {
+    fn default() -> Self {
+        PriceSource::Last
+    }
+}
+
+// FeedConfig describes one price feed served by this node
+#[derive(Deserialize, Clone)]
+pub struct FeedConfig {
+    pub coin_name: String,
+    pub asset: Option<String>, //symbol of the coin like BTC, which DEX pools are matched against
+    pub contract_address: String,
+    pub mappings: BTreeMap<String, Vec<String>>,
+    pub feed_interval: u64,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default = "default_trim_ratio")]
+    pub trim_ratio: f64, //ratio of volume trimmed from each side, for trimmed_mean
+    #[serde(default = "default_twap_samples")]
+    pub twap_samples: usize, //number of recent prices averaged, for twap
+    #[serde(default = "default_min_volume")]
+    pub min_volume: f64, //minimal volume of accepted pairs in base units, below it no price is fed
+    #[serde(default)]
+    pub price_source: PriceSource,
+    pub max_spread: Option<f64>, //pairs with a wider spread relative to the mid price are rejected
+    #[serde(default)]
+    pub spread_weighted: bool, //scale weights of pairs by their confidence, 1 - spread / max_spread
+}
+
+impl FeedConfig {
+    // asset falls back to coin_name if not specified
+    pub fn asset(&self) -> &str {
+        self.asset.as_deref().unwrap_or(&self.coin_name)
+    }
+}
+
+fn default_trim_ratio() -> f64 {
+    0.1
+}
+
+fn default_twap_samples() -> usize {
+    10
+}
+
+fn default_min_volume() -> f64 {
+    1.0
+}
+
+// HttpConfig tunes the http client shared by REST fetchers
+#[derive(Deserialize, Clone, Debug)]
+pub struct HttpConfig {
+    #[serde(default = "default_http_timeout")]
+    pub timeout: u64, //in seconds, of a whole request
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64, //in seconds
+    pub proxy: Option<String>, //e.g. http://127.0.0.1:8080
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    #[serde(default = "default_gzip")]
+    pub gzip: bool,
+    #[serde(default = "default_max_per_host")]
+    pub max_per_host: usize, //max in-flight requests to one host
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            timeout: default_http_timeout(),
+            connect_timeout: default_connect_timeout(),
+            proxy: None,
+            user_agent: default_user_agent(),
+            gzip: default_gzip(),
+            max_per_host: default_max_per_host(),
+        }
+    }
+}
+
+fn default_http_timeout() -> u64 {
+    5
+}
+
+fn default_connect_timeout() -> u64 {
+    3
+}
+
+fn default_user_agent() -> String {
+    format!("ti-oracle/{}", env!("CARGO_PKG_VERSION"))
+}
+
+fn default_gzip() -> bool {
+    true
+}
+
+fn default_max_per_host() -> usize {
+    4
+}
+
+// DiscoveryConfig tunes how peers are found besides the static ones
+#[derive(Deserialize, Clone, Debug)]
+pub struct DiscoveryConfig {
+    #[serde(default)]
+    pub mdns: bool, //discover peers in the local network
+    #[serde(default = "default_discovery_interval")]
+    pub interval: u64, //in seconds, between advertising and resolving nodes in the DHT
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            mdns: false,
+            interval: default_discovery_interval(),
+        }
+    }
+}
+
+fn default_discovery_interval() -> u64 {
+    30
+}
+
+// ResilienceConfig guards calls of fetchers against failing or throttling exchanges
+#[derive(Deserialize, Clone, Debug)]
+pub struct ResilienceConfig {
+    #[serde(default = "default_retries")]
+    pub retries: u32, //retries of transient errors in one round
+    #[serde(default = "default_backoff")]
+    pub backoff: u64, //in ms, base of the jittered exponential backoff
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32, //consecutive failed calls which open the circuit breaker
+    #[serde(default = "default_open_duration")]
+    pub open_duration: u64, //in seconds, an exchange is excluded while its breaker is open
+    #[serde(default)]
+    pub rate_limits: BTreeMap<String, RateLimit>, //exchange -> limit, overriding the published one
+}
+
+// RateLimit is a token bucket, refilled by `rate` tokens per second up to `burst`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+impl Default for ResilienceConfig {
+    fn default() -> Self {
+        ResilienceConfig {
+            retries: default_retries(),
+            backoff: default_backoff(),
+            failure_threshold: default_failure_threshold(),
+            open_duration: default_open_duration(),
+            rate_limits: BTreeMap::new(),
+        }
+    }
+}
+
+impl ResilienceConfig {
+    pub fn rate_limit(&self, exchange: &str) -> RateLimit {
+        by_exchange(&self.rate_limits, exchange)
+            .unwrap_or_else(|| resilience::published_limit(exchange))
+    }
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+fn default_backoff() -> u64 {
+    250
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_open_duration() -> u64 {
+    60
+}
+
+// VenueMode decides whether tickers of all symbols are fetched by one request, or one request per symbol
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VenueMode {
+    Bulk,
+    PerSymbol,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampFormat {
+    Ms,
+    S,
+    Rfc3339,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        TimestampFormat::Ms
+    }
+}
+
+// VenueConfig describes a JSON ticker endpoint, fetched by the generic REST exchange
+#[derive(Deserialize, Clone, Debug)]
+pub struct VenueConfig {
+    pub url: String, //`{symbol}` is replaced by each symbol in per_symbol mode
+    pub mode: VenueMode,
+    pub tickers: Option<String>, //selector of tickers in the response, the root if absent
+    pub symbol: Option<String>, //selector of the symbol of each ticker in bulk mode, keys if tickers is an object
+    pub price: String,
+    pub volume: String,
+    pub bid: Option<String>,       //selector of the best bid, if reported
+    pub ask: Option<String>,       //selector of the best ask, if reported
+    pub timestamp: Option<String>, //the Date header of the response is used if absent
+    #[serde(default)]
+    pub timestamp_format: TimestampFormat,
+    #[serde(default)]
+    pub volume_unit: Side, //whether volume is counted in the base or quote asset
+}
+
 #[derive(Deserialize, Clone)]
 pub struct Config {
     pub listen_address: String,
     pub web_address: String,
     pub log_level: String,
     pub eth_rpc_url: String,
-    pub contract_address: String,
+    #[serde(default = "default_dex_rpc_url")]
+    pub dex_rpc_url: String,
+    #[serde(default)]
+    pub dex_mode: DexMode,
+    pub dex_block: Option<u64>, //read pools at a pinned block, or the latest one if absent
+    #[serde(default)]
+    pub streaming: bool, //subscribe tickers of centralized exchanges by websocket
+    #[serde(default = "default_stream_max_age")]
+    pub stream_max_age: u64, //in seconds, fallback to REST if cached tickers are older
+    #[serde(default)]
+    pub max_age: BTreeMap<String, u64>, //exchange -> seconds, quotes older than this are dropped
+    #[serde(default = "default_max_age")]
+    pub default_max_age: u64, //in seconds, for exchanges absent in max_age
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub resilience: ResilienceConfig,
+    #[serde(default)]
+    pub venues: BTreeMap<String, VenueConfig>, //exchange -> endpoint, added without recompiling
+    #[serde(default)]
+    pub exchanges: BTreeMap<String, bool>, //exchange -> enabled, all exchanges are enabled if absent
     pub private_key: String,
-    pub coin_name: String,
     pub peers: Vec<String>,
-    pub mappings: BTreeMap<String, Vec<String>>,
-    pub feed_interval: u64,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    pub feeds: Vec<FeedConfig>,
     pub fee_per_gas: f64,
 }
 
+fn default_dex_rpc_url() -> String {
+    "https://cloudflare-eth.com".into()
+}
+
+fn default_stream_max_age() -> u64 {
+    30
+}
+
+fn default_max_age() -> u64 {
+    300
+}
+
 impl Config {
     pub fn new(file: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let mut c = config::Config::new();
         c.merge(config::File::with_name(file))?;
-        Ok(c.try_into()?)
+        let cfg: Config = c.try_into()?;
+        if cfg.http.max_per_host == 0 {
+            return Err("http max_per_host must be positive".into());
+        }
+        for (exchange, limit) in &cfg.resilience.rate_limits {
+            if limit.rate <= 0.0 || limit.burst < 1.0 {
+                return Err(format!("invalid rate limit of {}", exchange).into());
+            }
+        }
+        for (name, venue) in &cfg.venues {
+            if aggregator::canonical_name(name) != *name
+                || aggregator::EXCHANGES.iter().any(|r| r.name == name)
+            {
+                return Err(format!("venue {} conflicts with a built-in exchange", name).into());
+            }
+            if let Err(err) = rest::new(name, venue, Default::default()) {
+                return Err(format!("invalid venue {}: {}", name, err).into());
+            }
+        }
+        if let Some(proxy) = &cfg.http.proxy {
+            reqwest::Proxy::all(proxy)?;
+        }
+        if cfg.discovery.interval == 0 {
+            return Err("discovery interval must be positive".into());
+        }
+        if cfg.feeds.is_empty() {
+            return Err("no feeds configured".into());
+        }
+        let mut seen = Vec::<&String>::new();
+        for feed in &cfg.feeds {
+            if seen.contains(&&feed.coin_name) {
+                return Err(format!("duplicated feed: {}", feed.coin_name).into());
+            }
+            seen.push(&feed.coin_name);
+            if feed.trim_ratio < 0.0 || feed.trim_ratio >= 0.5 {
+                return Err(format!("invalid trim_ratio of feed: {}", feed.coin_name).into());
+            }
+            if feed.twap_samples == 0 {
+                return Err(format!("invalid twap_samples of feed: {}", feed.coin_name).into());
+            }
+            if feed.min_volume.is_nan() || feed.min_volume < 0.0 {
+                return Err(format!("invalid min_volume of feed: {}", feed.coin_name).into());
+            }
+            if feed
+                .max_spread
+                .map_or(false, |max_spread| max_spread <= 0.0)
+            {
+                return Err(format!("invalid max_spread of feed: {}", feed.coin_name).into());
+            }
+            if feed.spread_weighted && feed.max_spread.is_none() {
+                return Err(format!(
+                    "spread_weighted requires max_spread in feed: {}",
+                    feed.coin_name
+                )
+                .into());
+            }
+            if feed.strategy == Strategy::LiquidityWeighted
+                && !feed.mappings.keys().any(|exchange| {
+                    cfg.exchange_enabled(exchange) && aggregator::supplies_depth(exchange)
+                })
+            {
+                return Err(format!(
+                    "liquidity_weighted requires an exchange reporting depth in feed: {}",
+                    feed.coin_name
+                )
+                .into());
+            }
+            for (exchange, symbols) in &feed.mappings {
+                for symbol in symbols {
+                    if let Err(err) = expression::parse(symbol) {
+                        return Err(format!(
+                            "invalid mapping `{}` of {} in feed {}: {:?}",
+                            symbol, exchange, feed.coin_name, err
+                        )
+                        .into());
+                    }
+                }
+            }
+        }
+        Ok(cfg)
     }
+
+    // max_age returns the max age in ms of quotes from the exchange
+    pub fn max_age(&self, exchange: &str) -> u64 {
+        by_exchange(&self.max_age, exchange).unwrap_or(self.default_max_age) * 1000
+    }
+
+    pub fn exchange_enabled(&self, exchange: &str) -> bool {
+        by_exchange(&self.exchanges, exchange).unwrap_or(true)
+    }
+}
+
+// look up a setting of the exchange, which may be keyed by any alias of it
+fn by_exchange<T: Copy>(settings: &BTreeMap<String, T>, exchange: &str) -> Option<T> {
+    let exchange = aggregator::canonical_name(exchange);
+    settings
+        .iter()
+        .find(|(name, _)| aggregator::canonical_name(name) == exchange)
+        .map(|(_, value)| *value)
 }
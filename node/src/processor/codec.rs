@@ -0,0 +1,228 @@
+use super::gossip::{CommandMessage, ValidateRequest, ValidateResponse};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use strum_macros::Display;
+
+// wire format of gossip messages:
+// one byte of schema version, the length of the body as u32 big endian, then the body in bincode;
+// messages of the legacy format are the bare JSON commands of old nodes, like `{"type":"VReq",...}`,
+// they start with `{` and are still decoded during the transition
+pub const LEGACY_JSON: u8 = 0;
+pub const VERSION: u8 = 1;
+const HEADER_LEN: usize = 5;
+
+#[derive(Debug, Display, PartialEq)]
+pub enum CodecError {
+    UnknownVersion(u8),
+    BadLength(String),
+    Malformed(String),
+}
+impl std::error::Error for CodecError {}
+
+// Frame is a signed message as carried over gossip, payload is the encoded command
+// in the format of version, which is the exact bytes signed by the signer;
+// legacy frames are not signed, so signer and sig are empty
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub version: u8,
+    pub payload: Vec<u8>,
+    pub signer: String,
+    pub sig: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    payload: Vec<u8>,
+    signer: String,
+    sig: String,
+}
+
+// legacy commands carry prices as decimal strings
+#[derive(Deserialize)]
+struct LegacyRequest {
+    coin: String,
+    price: String,
+    feed_count: u64,
+    timestamp: u64,
+}
+
+#[derive(Deserialize)]
+struct LegacyResponse {
+    coin: String,
+    price: String,
+    feed_count: u64,
+    sig: String,
+    timestamp: u64,
+    address: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum LegacyCommand {
+    VReq(LegacyRequest),
+    VResp(LegacyResponse),
+}
+
+fn legacy_price(price: &str) -> Result<u128, CodecError> {
+    price
+        .parse::<u128>()
+        .map_err(|err| CodecError::Malformed(format!("price {}: {}", price, err)))
+}
+
+fn malformed<E: ToString>(err: E) -> CodecError {
+    CodecError::Malformed(err.to_string())
+}
+
+pub fn encode_command(cmd: &CommandMessage) -> Vec<u8> {
+    bincode::serialize(cmd).unwrap()
+}
+
+pub fn decode_command(version: u8, payload: &[u8]) -> Result<CommandMessage, CodecError> {
+    match version {
+        VERSION => bincode::deserialize(payload).map_err(malformed),
+        LEGACY_JSON => match serde_json::from_slice(payload).map_err(malformed)? {
+            LegacyCommand::VReq(req) => Ok(CommandMessage::VReq(ValidateRequest {
+                price: legacy_price(&req.price)?,
+                coin: req.coin,
+                feed_count: req.feed_count,
+                timestamp: req.timestamp,
+                dispersion: 0.0,
+                sources: 0,
+            })),
+            LegacyCommand::VResp(resp) => Ok(CommandMessage::VResp(ValidateResponse {
+                price: legacy_price(&resp.price)?,
+                coin: resp.coin,
+                feed_count: resp.feed_count,
+                sig: resp.sig,
+                timestamp: resp.timestamp,
+                address: resp.address,
+            })),
+        },
+        _ => Err(CodecError::UnknownVersion(version)),
+    }
+}
+
+// encode_frame always writes the current version
+pub fn encode_frame(frame: &Frame) -> Vec<u8> {
+    let body = bincode::serialize(&Envelope {
+        payload: frame.payload.clone(),
+        signer: frame.signer.clone(),
+        sig: frame.sig.clone(),
+    })
+    .unwrap();
+    let mut data = Vec::with_capacity(HEADER_LEN + body.len());
+    data.push(VERSION);
+    data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    data.extend(body);
+    data
+}
+
+pub fn decode_frame(data: &[u8]) -> Result<Frame, CodecError> {
+    if data.first() == Some(&b'{') {
+        return Ok(Frame {
+            version: LEGACY_JSON,
+            payload: data.to_vec(),
+            signer: "".into(),
+            sig: "".into(),
+        });
+    }
+    if data.len() < HEADER_LEN {
+        return Err(CodecError::BadLength(format!("{} bytes", data.len())));
+    }
+    if data[0] != VERSION {
+        return Err(CodecError::UnknownVersion(data[0]));
+    }
+    let body_len = u32::from_be_bytes(data[1..HEADER_LEN].try_into().unwrap()) as usize;
+    if body_len != data.len() - HEADER_LEN {
+        return Err(CodecError::BadLength(format!(
+            "header {}, body {}",
+            body_len,
+            data.len() - HEADER_LEN
+        )));
+    }
+    let envelope: Envelope = bincode::deserialize(&data[HEADER_LEN..]).map_err(malformed)?;
+    Ok(Frame {
+        version: VERSION,
+        payload: envelope.payload,
+        signer: envelope.signer,
+        sig: envelope.sig,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> CommandMessage {
+        CommandMessage::VReq(ValidateRequest {
+            coin: "bitcoin".into(),
+            price: 2300000000000,
+            feed_count: 7,
+            timestamp: 1656587035,
+            dispersion: 0.001,
+            sources: 12,
+        })
+    }
+
+    #[test]
+    fn test_binary_frame() {
+        let frame = Frame {
+            version: VERSION,
+            payload: encode_command(&request()),
+            signer: "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266".into(),
+            sig: "abcd".into(),
+        };
+        let data = encode_frame(&frame);
+        assert_eq!(data[0], VERSION);
+        assert_eq!(decode_frame(&data), Ok(frame.clone()));
+        match decode_command(VERSION, &frame.payload) {
+            Ok(CommandMessage::VReq(req)) => assert_eq!(req.feed_count, 7),
+            _ => panic!("request expected"),
+        }
+        let mut unknown = data.clone();
+        unknown[0] = 9;
+        assert_eq!(decode_frame(&unknown), Err(CodecError::UnknownVersion(9)));
+        assert!(matches!(
+            decode_frame(&data[..data.len() - 1]),
+            Err(CodecError::BadLength(_))
+        ));
+        assert!(matches!(
+            decode_frame(&[1, 0]),
+            Err(CodecError::BadLength(_))
+        ));
+    }
+
+    #[test]
+    fn test_legacy_json() {
+        // messages as published by nodes of the baseline
+        let data = br#"{"type":"VReq","coin":"bitcoin","price":"2300000000000","feed_count":7,"timestamp":1656587035}"#;
+        let frame = decode_frame(data).unwrap();
+        assert_eq!(frame.version, LEGACY_JSON);
+        assert_eq!(frame.payload, data.to_vec());
+        assert_eq!(frame.signer, "");
+        match decode_command(frame.version, &frame.payload) {
+            Ok(CommandMessage::VReq(req)) => {
+                assert_eq!(req.price, 2300000000000);
+                assert_eq!(req.feed_count, 7);
+            }
+            _ => panic!("request expected"),
+        }
+        let data = br#"{"type":"VResp","coin":"bitcoin","price":"2300000000000","feed_count":7,"sig":"abcd","timestamp":1656587035,"address":"0xf39f"}"#;
+        match decode_command(LEGACY_JSON, &decode_frame(data).unwrap().payload) {
+            Ok(CommandMessage::VResp(resp)) => {
+                assert_eq!(resp.price, 2300000000000);
+                assert_eq!(resp.address, "0xf39f");
+            }
+            _ => panic!("response expected"),
+        }
+        let bad_price = br#"{"type":"VReq","coin":"bitcoin","price":"-1","feed_count":7,"timestamp":1656587035}"#;
+        assert!(matches!(
+            decode_command(LEGACY_JSON, bad_price),
+            Err(CodecError::Malformed(_))
+        ));
+        assert!(matches!(
+            decode_command(LEGACY_JSON, b"{not json"),
+            Err(CodecError::Malformed(_))
+        ));
+    }
+}
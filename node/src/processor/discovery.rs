@@ -0,0 +1,118 @@
+use crate::chains::eth;
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+
+// advertisements older than this are taken as gone, nodes advertise again every discovery interval
+pub const MAX_ADVERT_AGE: u64 = 3600;
+// clocks of nodes may differ a little, advertisements further ahead of now are refused
+pub const MAX_CLOCK_SKEW: u64 = 300;
+
+#[derive(Debug, Display, PartialEq)]
+pub enum AdvertError {
+    Malformed(String),
+    BadSignature(String),
+    Expired(String),
+    Future(String),
+}
+impl std::error::Error for AdvertError {}
+
+// Advertisement binds the eth address of a node to its peer id and listen addresses,
+// it is put into the DHT under the key of the eth address
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Advertisement {
+    pub address: String,
+    pub peer_id: String,
+    pub addrs: Vec<String>,
+    pub timestamp: u64, //in seconds
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedAdvertisement {
+    advert: Vec<u8>,
+    sig: String,
+}
+
+// record_key is the DHT key of the advertisement of a node
+pub fn record_key(address: &str) -> Vec<u8> {
+    format!("/ti-oracle/node/{}", address.to_lowercase()).into_bytes()
+}
+
+// seal signs the bincode of an advertisement by the eth key of the node
+pub fn seal(advert: &Advertisement, private_key: &str) -> Vec<u8> {
+    let data = bincode::serialize(advert).unwrap();
+    let (sig, _) = eth::sign_message(private_key, &data);
+    bincode::serialize(&SignedAdvertisement {
+        advert: data,
+        sig: sig,
+    })
+    .unwrap()
+}
+
+// open returns the advertisement if it is signed by the node it advertises, and neither expired
+// nor ahead of now
+pub fn open(data: &[u8], now: u64) -> Result<Advertisement, AdvertError> {
+    let signed: SignedAdvertisement =
+        bincode::deserialize(data).map_err(|err| AdvertError::Malformed(err.to_string()))?;
+    let advert: Advertisement = bincode::deserialize(&signed.advert)
+        .map_err(|err| AdvertError::Malformed(err.to_string()))?;
+    if !eth::verify_message(&signed.sig, &signed.advert, &advert.address) {
+        return Err(AdvertError::BadSignature(advert.address));
+    }
+    if advert.timestamp.saturating_add(MAX_ADVERT_AGE) < now {
+        return Err(AdvertError::Expired(format!(
+            "{} at {}",
+            advert.address, advert.timestamp
+        )));
+    }
+    if advert.timestamp > now.saturating_add(MAX_CLOCK_SKEW) {
+        return Err(AdvertError::Future(format!(
+            "{} at {}",
+            advert.address, advert.timestamp
+        )));
+    }
+    Ok(advert)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_advertisement() {
+        let pk = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+        let mut advert = Advertisement {
+            address: eth::pk_to_address(pk.into()),
+            peer_id: "12D3KooWD3eckifWpRn9wQpMG9R9hX3sD158z7EqHWmweQAJU5SA".into(),
+            addrs: vec!["/ip4/10.0.0.1/tcp/3000".into()],
+            timestamp: 1656587035,
+        };
+        let data = seal(&advert, pk);
+        assert_eq!(open(&data, 1656587035), Ok(advert.clone()));
+        assert!(matches!(
+            open(&data, 1656587035 + MAX_ADVERT_AGE + 1),
+            Err(AdvertError::Expired(_))
+        ));
+        // a timestamp ahead of now is taken within the clock skew only, the largest one does not overflow
+        assert!(open(&data, 1656587035 - MAX_CLOCK_SKEW).is_ok());
+        assert!(matches!(
+            open(&data, 1656587035 - MAX_CLOCK_SKEW - 1),
+            Err(AdvertError::Future(_))
+        ));
+        let mut future = advert.clone();
+        future.timestamp = u64::MAX;
+        assert!(matches!(
+            open(&seal(&future, pk), 1656587035),
+            Err(AdvertError::Future(_))
+        ));
+        // an advertisement of another node signed by this key is refused
+        advert.address = "0x70997970c51812dc3a010c7d01b50e0d17dc79c8".into();
+        assert!(matches!(
+            open(&seal(&advert, pk), 1656587035),
+            Err(AdvertError::BadSignature(_))
+        ));
+        assert!(matches!(
+            open(b"garbage", 1656587035),
+            Err(AdvertError::Malformed(_))
+        ));
+        assert_eq!(record_key("0xABC"), b"/ti-oracle/node/0xabc".to_vec());
+    }
+}
@@ -2,35 +2,49 @@ use crate::chains::eth;
 use crate::flags::Config;
 use crate::processor::utils;
 use async_std::io;
-use futures::channel::mpsc::Receiver;
+use futures::channel::mpsc::{self, Receiver, UnboundedReceiver, UnboundedSender};
 use futures::{prelude::*, select};
+use futures_timer::Delay;
 use libp2p::gossipsub::error::PublishError;
 use libp2p::gossipsub::GossipsubEvent;
 use libp2p::gossipsub::IdentTopic;
 use libp2p::gossipsub::MessageId;
+use libp2p::kad::record::Key;
+use libp2p::kad::{GetRecordOk, KademliaEvent, QueryResult, Quorum, Record};
+use libp2p::mdns::MdnsEvent;
+use libp2p::request_response::{RequestResponseEvent, RequestResponseMessage, ResponseChannel};
 use libp2p::swarm::SwarmEvent;
 use libp2p::Multiaddr;
+use libp2p::PeerId;
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
+use super::codec::{self, Frame};
+use super::discovery::{self, Advertisement};
+use super::swarm::{OracleBehaviour, OracleEvent};
 use super::web::SharedState;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ValidateRequest {
     pub coin: String,
-    pub price: String,
+    pub price: u128,
     pub feed_count: u64,
     pub timestamp: u64,
+    #[serde(default)]
+    pub dispersion: f64, //standard deviation of sources of the requester, relative to its price
+    #[serde(default)]
+    pub sources: usize, //pairs aggregated by the requester
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ValidateResponse {
     pub coin: String,
-    pub price: String,
+    pub price: u128,
     pub feed_count: u64,
     pub sig: String,
     pub timestamp: u64,
@@ -39,55 +53,408 @@ pub struct ValidateResponse {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RefreshPrice {
+    pub coin: String,
     pub price: String,
     pub timestamp: u64,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(tag = "type")]
+// the legacy JSON tagged by "type" is decoded in codec
+#[derive(Serialize, Deserialize, Debug)]
 pub enum CommandMessage {
     VReq(ValidateRequest),
     VResp(ValidateResponse),
 }
 
+impl CommandMessage {
+    pub fn coin(&self) -> &String {
+        match self {
+            CommandMessage::VReq(req) => &req.coin,
+            CommandMessage::VResp(resp) => &resp.coin,
+        }
+    }
+}
+
+// a command message signed by the eth key of its sender goes over gossip in a codec frame,
+// the payload is kept as sent so that the signature is checked over the exact bytes signed
+pub fn seal(cmd: &CommandMessage, private_key: &str) -> Frame {
+    let payload = codec::encode_command(cmd);
+    let (sig, signer) = eth::sign_message(private_key, &payload);
+    Frame {
+        version: codec::VERSION,
+        payload: payload,
+        signer: signer,
+        sig: sig,
+    }
+}
+
+// open returns the command and its signer if the signature matches the signer;
+// legacy frames are not signed, only their responses are taken, by the signature of the price
+pub fn open(frame: &Frame) -> Result<(CommandMessage, String), String> {
+    if frame.version == codec::LEGACY_JSON {
+        return match codec::decode_command(frame.version, &frame.payload) {
+            Ok(CommandMessage::VResp(resp)) => {
+                let signed = eth::verify_sig(
+                    resp.sig.clone(),
+                    resp.coin.clone(),
+                    resp.price,
+                    resp.timestamp,
+                    resp.address.clone(),
+                );
+                if !signed {
+                    return Err(format!("bad price signature from {}", resp.address));
+                }
+                let signer = resp.address.clone();
+                Ok((CommandMessage::VResp(resp), signer))
+            }
+            Ok(CommandMessage::VReq(req)) => {
+                Err(format!("unsigned legacy request of {}", req.coin))
+            }
+            Err(err) => Err(err.to_string()),
+        };
+    }
+    if !eth::verify_message(&frame.sig, &frame.payload, &frame.signer) {
+        return Err(format!("bad signature from {}", frame.signer));
+    }
+    let cmd =
+        codec::decode_command(frame.version, &frame.payload).map_err(|err| err.to_string())?;
+    Ok((cmd, frame.signer.clone()))
+}
+
 pub enum LocalCommand {
     VReq(ValidateRequest),
     RefreshReq(RefreshPrice),
 }
 
+// max relative difference between the price requested and the local one
+const MAX_PRICE_DIFF: f64 = 0.01;
+// addresses refused by the contract are not looked up again for this long, in ms
+const NODE_REFUSAL_TTL: u64 = 60 * 1000;
+// lookups of unknown signers running at once, messages of others are dropped until some finish
+const MAX_PENDING_LOOKUPS: usize = 16;
+// leader checks running at once, requests beyond are refused until some finish
+const MAX_PENDING_CHECKS: usize = 16;
+
+// NodeLookup is the answer of the contract whether an address is a node of a coin, None if the query failed
+struct NodeLookup {
+    coin: String,
+    address: String,
+    allowed: Option<bool>,
+}
+
+// Reply tells where the response of a request goes, to its topic or back to the requester
+enum Reply {
+    Gossip,
+    Direct(PeerId, ResponseChannel<Vec<u8>>),
+}
+
+// LeaderCheck is the answer of the contract whether the requester leads the feed count of a request,
+// along with the leaders of the coin looked up meanwhile
+struct LeaderCheck {
+    request: ValidateRequest,
+    price_local: u128, //local price when the request was checked, which is signed
+    leaders: eth::LeaderCache,
+    result: Result<(), eth::LeaderError>,
+    reply: Reply,
+}
+
 pub type ValidationBucket = Arc<Mutex<BTreeMap<u64, Vec<ValidateResponse>>>>;
 pub struct P2PMessageProcessor {
-    swarm: libp2p::Swarm<libp2p::gossipsub::Gossipsub>,
-    topic: IdentTopic,
+    swarm: libp2p::Swarm<OracleBehaviour>,
+    topics: Vec<IdentTopic>, //one topic for each coin
     recv: Receiver<LocalCommand>,
-    last_seen_price: Arc<Mutex<u128>>,
-    bucket: ValidationBucket,
+    last_seen_price: Arc<Mutex<BTreeMap<String, u128>>>, //coin -> price
+    buckets: BTreeMap<String, ValidationBucket>,         //coin -> bucket
+    oracles: BTreeMap<String, eth::OracleStub>,          //coin -> contract, to query nodes
+    node_peers: BTreeMap<String, (PeerId, u64)>, //eth address -> peer and timestamp of its latest advertisement verified
+    last_signed: BTreeMap<String, (u64, u64)>, //coin -> feed count and timestamp of the request signed last
+    leaders: BTreeMap<String, eth::LeaderCache>, //coin -> leaders decided by the contract
+    lookup_send: UnboundedSender<NodeLookup>,
+    lookup_recv: UnboundedReceiver<NodeLookup>,
+    pending_lookups: BTreeSet<(String, String)>, //coin and address being looked up
+    check_send: UnboundedSender<LeaderCheck>,
+    check_recv: UnboundedReceiver<LeaderCheck>,
+    pending_checks: usize,                          //leader checks running
+    refused_nodes: BTreeMap<(String, String), u64>, //coin and address -> ms until which it is refused
     s_state: SharedState,
 }
 
 pub fn new(
-    swarm: libp2p::Swarm<libp2p::gossipsub::Gossipsub>,
-    topic: IdentTopic,
+    swarm: libp2p::Swarm<OracleBehaviour>,
+    topics: Vec<IdentTopic>,
     recv: Receiver<LocalCommand>,
-    bucket: ValidationBucket,
+    buckets: BTreeMap<String, ValidationBucket>,
+    oracles: BTreeMap<String, eth::OracleStub>,
     s_state: SharedState,
 ) -> P2PMessageProcessor {
+    let (lookup_send, lookup_recv) = mpsc::unbounded();
+    let (check_send, check_recv) = mpsc::unbounded();
     P2PMessageProcessor {
         swarm: swarm,
-        topic: topic,
+        topics: topics,
         recv: recv,
-        last_seen_price: Arc::<Mutex<u128>>::new(Mutex::new(0)),
-        bucket: bucket,
+        last_seen_price: Arc::new(Mutex::new(BTreeMap::new())),
+        buckets: buckets,
+        oracles: oracles,
+        node_peers: BTreeMap::new(),
+        last_signed: BTreeMap::new(),
+        leaders: BTreeMap::new(),
+        lookup_send: lookup_send,
+        lookup_recv: lookup_recv,
+        pending_lookups: BTreeSet::new(),
+        check_send: check_send,
+        check_recv: check_recv,
+        pending_checks: 0,
+        refused_nodes: BTreeMap::new(),
         s_state: s_state,
     }
 }
 
 impl P2PMessageProcessor {
-    //helper to send string message to p2p network
-    fn publish_txt(&mut self, txt: String) -> Result<MessageId, PublishError> {
+    //helper to send a frame to the topic of one coin
+    fn publish_frame(&mut self, coin: &String, data: Vec<u8>) -> Result<MessageId, PublishError> {
         self.swarm
             .behaviour_mut()
-            .publish(self.topic.clone(), txt.as_bytes())
+            .gossipsub
+            .publish(IdentTopic::new(coin.clone()), data)
+    }
+
+    // dial_peers reaches out to the bootstrap peers in config
+    fn dial_peers(&mut self, cfg: &Config) {
+        for peer_node in &cfg.peers {
+            if peer_node.len() == 0 {
+                continue;
+            }
+            let address: Multiaddr = peer_node.parse().expect("User to provide valid address.");
+            match self.swarm.dial(address.clone()) {
+                Ok(_) => info!("Dialed {:?}", address),
+                Err(e) => warn!("Dial {:?} failed: {:?}", address, e),
+            };
+        }
+    }
+
+    // discover advertises this node in the DHT, then looks up whitelisted nodes not connected yet
+    fn discover(&mut self, cfg: &Config, self_eth_address: &String) {
+        if self.swarm.connected_peers().next().is_none() {
+            self.dial_peers(cfg);
+        }
+        if let Err(err) = self.swarm.behaviour_mut().kademlia.bootstrap() {
+            debug!("kademlia bootstrap fail: {:?}", err);
+        }
+        let advert = Advertisement {
+            address: self_eth_address.clone(),
+            peer_id: self.swarm.local_peer_id().to_string(),
+            addrs: self
+                .swarm
+                .listeners()
+                .map(|addr| addr.to_string())
+                .collect(),
+            timestamp: utils::timestamp() / 1000,
+        };
+        let record = Record::new(
+            Key::new(&discovery::record_key(self_eth_address)),
+            discovery::seal(&advert, &cfg.private_key),
+        );
+        if let Err(err) = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .put_record(record, Quorum::One)
+        {
+            debug!("advertise fail: {:?}", err);
+        }
+        // a node is connected if the peer of its advertisement is, by the connections of the swarm
+        let connected: BTreeSet<String> = self
+            .node_peers
+            .iter()
+            .filter(|(_, (peer, _))| self.swarm.is_connected(peer))
+            .map(|(address, _)| address.clone())
+            .collect();
+        let nodes: BTreeSet<String> = self
+            .s_state
+            .lock()
+            .unwrap()
+            .nodes
+            .values()
+            .flat_map(|nodes| nodes.iter().filter(|(_, allowed)| **allowed))
+            .map(|(address, _)| address.clone())
+            .collect();
+        for address in nodes {
+            if address == *self_eth_address || connected.contains(&address) {
+                continue;
+            }
+            self.swarm
+                .behaviour_mut()
+                .kademlia
+                .get_record(&Key::new(&discovery::record_key(&address)), Quorum::One);
+        }
+    }
+
+    // on_advert maps a node to its peer id by an advertisement found in the DHT, then dials it;
+    // an advertisement older than the one known of the node is a replay, and ignored
+    fn on_advert(&mut self, data: &[u8]) {
+        let advert = match discovery::open(data, utils::timestamp() / 1000) {
+            Ok(advert) => advert,
+            Err(err) => {
+                warn!("advertisement error: {:?}", err);
+                return;
+            }
+        };
+        let peer = match PeerId::from_str(&advert.peer_id) {
+            Ok(peer) => peer,
+            Err(err) => {
+                warn!("advertisement of {} error: {:?}", advert.address, err);
+                return;
+            }
+        };
+        if peer == *self.swarm.local_peer_id() {
+            return;
+        }
+        // only nodes whitelisted by the contract of some coin are mapped
+        let coins: Vec<String> = self.buckets.keys().cloned().collect();
+        if !coins.iter().any(|coin| self.is_node(coin, &advert.address)) {
+            warn!("advertisement from unexpected node: {}", advert.address);
+            return;
+        }
+        let address = advert.address.to_lowercase();
+        if let Some((known, timestamp)) = self.node_peers.get(&address) {
+            if *timestamp > advert.timestamp {
+                debug!("advertisement of {} older than {:?}", address, known);
+                return;
+            }
+        }
+        info!("node {} is peer {:?}", address, peer);
+        self.node_peers.insert(address, (peer, advert.timestamp));
+        for addr in advert
+            .addrs
+            .iter()
+            .filter_map(|addr| addr.parse::<Multiaddr>().ok())
+        {
+            self.swarm.behaviour_mut().kademlia.add_address(&peer, addr);
+        }
+        if !self.swarm.is_connected(&peer) {
+            if let Err(err) = self.swarm.dial(peer) {
+                warn!("Dial node {} failed: {:?}", advert.address, err);
+            }
+        }
+    }
+
+    // request_peers asks every connected peer for signatures directly,
+    // then returns eth addresses of the peers asked, as far as their advertisements are known
+    fn request_peers(&mut self, data: &Vec<u8>) -> BTreeSet<String> {
+        let peers: Vec<PeerId> = self.swarm.connected_peers().cloned().collect();
+        for peer in &peers {
+            self.swarm
+                .behaviour_mut()
+                .request_response
+                .send_request(peer, data.clone());
+        }
+        self.node_peers
+            .iter()
+            .filter(|(_, (peer, _))| peers.contains(peer))
+            .map(|(address, _)| address.clone())
+            .collect()
+    }
+
+    // unreached_nodes returns whitelisted nodes of a coin not asked directly,
+    // which have to be reached by gossip, unknown whitelist takes all of them as unreached
+    fn unreached_nodes(
+        &self,
+        coin: &String,
+        asked: &BTreeSet<String>,
+        self_address: &String,
+    ) -> usize {
+        match self.s_state.lock().unwrap().nodes.get(coin) {
+            Some(nodes) => nodes
+                .iter()
+                .filter(|(address, allowed)| {
+                    **allowed && *address != self_address && !asked.contains(*address)
+                })
+                .count(),
+            None => usize::MAX,
+        }
+    }
+
+    // is_node tells whether an address is a whitelisted node of the contract of a coin, from the cache
+    // kept by events watch; an address never seen is refused while it is looked up off the loop
+    fn is_node(&mut self, coin: &String, address: &String) -> bool {
+        let address = address.to_lowercase();
+        let cached = self
+            .s_state
+            .lock()
+            .unwrap()
+            .nodes
+            .get(coin)
+            .and_then(|nodes| nodes.get(&address).copied());
+        if let Some(allowed) = cached {
+            return allowed;
+        }
+        let key = (coin.clone(), address);
+        let refused = self.refused_nodes.get(&key);
+        if refused.map_or(false, |until| *until > utils::timestamp())
+            || self.pending_lookups.contains(&key)
+            || self.pending_lookups.len() >= MAX_PENDING_LOOKUPS
+        {
+            return false;
+        }
+        let oracle_stub = match self.oracles.get(coin) {
+            Some(oracle_stub) => oracle_stub.clone(),
+            None => return false,
+        };
+        self.pending_lookups.insert(key.clone());
+        let lookup_send = self.lookup_send.clone();
+        tokio::task::spawn(async move {
+            let (coin, address) = key;
+            let allowed = eth::query_node(&oracle_stub, &address).await;
+            let lookup = NodeLookup {
+                coin: coin,
+                address: address,
+                allowed: allowed,
+            };
+            if lookup_send.unbounded_send(lookup).is_err() {
+                warn!("node lookup dropped");
+            }
+        });
+        false
+    }
+
+    // on_lookup caches the answer of the contract, nodes are kept along with those of events watch,
+    // while refusals and failed queries expire, so that an RPC is not queried again for each message
+    fn on_lookup(&mut self, lookup: NodeLookup) {
+        let key = (lookup.coin, lookup.address);
+        self.pending_lookups.remove(&key);
+        let now = utils::timestamp();
+        self.refused_nodes.retain(|_, until| *until > now);
+        if lookup.allowed == Some(true) {
+            self.s_state
+                .lock()
+                .unwrap()
+                .nodes
+                .entry(key.0)
+                .or_default()
+                .insert(key.1, true);
+        } else {
+            self.refused_nodes.insert(key, now + NODE_REFUSAL_TTL);
+        }
+    }
+
+    // open_frame decodes a frame then returns its command and signer,
+    // if the signature is valid and the signer is a whitelisted node
+    fn open_frame(&mut self, data: &[u8]) -> Option<(CommandMessage, String)> {
+        let cmd_result = codec::decode_frame(data)
+            .map_err(|err| err.to_string())
+            .and_then(|frame| open(&frame));
+        let (cmd, signer) = match cmd_result {
+            Ok(opened) => opened,
+            Err(err) => {
+                warn!("message error:{:?}", err);
+                return None;
+            }
+        };
+        if !self.is_node(cmd.coin(), &signer) {
+            warn!("message of {} from unexpected node: {}", cmd.coin(), signer);
+            return None;
+        }
+        Some((cmd, signer))
     }
 
     // handle incoming events from p2p network
@@ -95,80 +462,139 @@ impl P2PMessageProcessor {
         // for debug usage
         let mut stdin = io::BufReader::new(io::stdin()).lines().fuse();
         let self_eth_address = eth::pk_to_address(cfg.private_key.clone());
+        let discovery_interval = Duration::from_secs(cfg.discovery.interval);
+        let mut discovery_delay = Delay::new(discovery_interval).fuse();
         // Kick it off
         loop {
             select! {
                 local_cmd = self.recv.select_next_some() => {
                     match local_cmd {
                         LocalCommand::VReq(valid_req) => {
+                            let coin = valid_req.coin.clone();
                             let cmd_req = CommandMessage::VReq(valid_req);
-                            let data = serde_json::to_string(&cmd_req).unwrap();
-                            debug!("local command {:}", data);
-                            if let Err(e) = self.publish_txt(data) {
+                            debug!("local command {:?}", cmd_req);
+                            let data = codec::encode_frame(&seal(&cmd_req, &cfg.private_key));
+                            let asked = self.request_peers(&data);
+                            let unreached = self.unreached_nodes(&coin, &asked, &self_eth_address);
+                            if unreached == 0 {
+                                continue;
+                            }
+                            debug!("ask {} nodes directly, the others by gossip", asked.len());
+                            if let Err(e) = self.publish_frame(&coin, data) {
                                 warn!("Publish feed request error:{:?}", e);
                                 //try reconnect
-                                for peer_node in &cfg.peers {
-                                    if peer_node.len() == 0 {
-                                        continue;
-                                    }
-                                    let address: Multiaddr = peer_node.parse().expect("User to provide valid address.");
-                                    match self.swarm.dial(address.clone()) {
-                                        Ok(_) => info!("Dialed {:?}", address),
-                                        Err(e) => warn!("Dial {:?} failed: {:?}", address, e),
-                                    };
-                                }
+                                self.dial_peers(&cfg);
                             }
                         },
                         LocalCommand::RefreshReq(refresh_req) => {
                             debug!("local command: {:?}", refresh_req);
-                            *self.last_seen_price.lock().unwrap() = refresh_req.price.parse::<u128>().unwrap();
+                            match refresh_req.price.parse::<u128>() {
+                                Ok(price) => {
+                                    self.last_seen_price.lock().unwrap().insert(refresh_req.coin, price);
+                                },
+                                Err(err) => warn!("invalid price {} of {}: {:?}", refresh_req.price, refresh_req.coin, err),
+                            }
                         }
                     }
 
                 },
+                lookup = self.lookup_recv.select_next_some() => {
+                    self.on_lookup(lookup);
+                },
+                check = self.check_recv.select_next_some() => {
+                    self.on_leader_check(check, &cfg);
+                },
+                _ = discovery_delay => {
+                    self.discover(&cfg, &self_eth_address);
+                    discovery_delay = Delay::new(discovery_interval).fuse();
+                },
                 line = stdin.select_next_some() => {
-                    if let Err(e) = self.publish_txt(line.expect("Stdin not to close"))
-                    {
-                        warn!("Publish debug info error: {:?}", e);
+                    let line = line.expect("Stdin not to close");
+                    for topic in self.topics.clone() {
+                        if let Err(e) = self.swarm.behaviour_mut().gossipsub.publish(topic, line.as_bytes())
+                        {
+                            warn!("Publish debug info error: {:?}", e);
+                        }
                     }
                 },
                 event = self.swarm.select_next_some() => match event {
-                    SwarmEvent::Behaviour(GossipsubEvent::Message {
+                    SwarmEvent::Behaviour(OracleEvent::Gossipsub(GossipsubEvent::Message {
                         propagation_source: peer_id,
                         message_id: id,
                         message,
-                    }) => {
-                        let msg_json = String::from_utf8_lossy(&message.data);
+                    })) => {
                         debug!(
-                        "Got message: {} with id: {} from peer: {:?}",
-                        msg_json,
+                        "Got message: {} bytes with id: {} from peer: {:?}",
+                        message.data.len(),
                         id,
                         peer_id);
-                        let cmd_result:Result<CommandMessage, serde_json::Error>= serde_json::from_str(&msg_json);
-                        if cmd_result.is_ok() {
-                            let cmd_result = cmd_result.unwrap();
-                            match cmd_result{
-                                CommandMessage::VReq(valid_req) => {
-                                    self.sign_and_sendresponse(valid_req, &cfg).await;
-                                },
-                                CommandMessage::VResp(valid_resps) => {
-                                    info!("validate price response {:?}", valid_resps);
-                                    let ts = utils::timestamp();
-                                    self.s_state.lock().unwrap().peers.insert(valid_resps.address.clone(), ts);
-                                    let mut v_bucket = self.bucket.lock().unwrap();
-                                    if !v_bucket.contains_key(&valid_resps.feed_count) {
-                                        v_bucket.insert(valid_resps.feed_count, Vec::<ValidateResponse>::new());
-                                    }
-                                    let round_collection = v_bucket.get_mut(&valid_resps.feed_count).unwrap();
-                                    let check_dup = round_collection.iter().find(|x| x.address == valid_resps.address);
-                                    if check_dup.is_none() && valid_resps.address != self_eth_address {
-                                        round_collection.push(valid_resps);
-                                    }
-                                    //info!("p2p bucket size:{}", v_bucket.len());
+                        // a signed frame may be relayed or replayed by any peer, so it maps no peer to its signer
+                        let (cmd, signer) = match self.open_frame(&message.data) {
+                            Some(opened) => opened,
+                            None => continue,
+                        };
+                        match cmd {
+                            CommandMessage::VReq(valid_req) => {
+                                self.check_request(valid_req, &signer, Reply::Gossip);
+                            },
+                            CommandMessage::VResp(valid_resps) => {
+                                self.collect_response(valid_resps, &signer, &self_eth_address);
+                            },
+                        }
+                    },
+                    SwarmEvent::Behaviour(OracleEvent::RequestResponse(RequestResponseEvent::Message {
+                        peer,
+                        message,
+                    })) => match message {
+                        RequestResponseMessage::Request { request, channel, .. } => {
+                            let reply = Reply::Direct(peer, channel);
+                            match self.open_frame(&request) {
+                                Some((CommandMessage::VReq(valid_req), signer)) => {
+                                    self.check_request(valid_req, &signer, reply);
                                 },
+                                _ => self.send_reply(reply, None),
+                            }
+                        },
+                        RequestResponseMessage::Response { response, .. } => {
+                            if response.is_empty() {
+                                debug!("request refused by {:?}", peer);
+                                continue;
+                            }
+                            if let Some((CommandMessage::VResp(valid_resps), signer)) = self.open_frame(&response) {
+                                self.collect_response(valid_resps, &signer, &self_eth_address);
+                            }
+                        },
+                    },
+                    SwarmEvent::Behaviour(OracleEvent::RequestResponse(RequestResponseEvent::OutboundFailure {
+                        peer,
+                        error,
+                        ..
+                    })) => {
+                        debug!("request to {:?} fail: {:?}", peer, error);
+                    },
+                    SwarmEvent::Behaviour(OracleEvent::Kademlia(KademliaEvent::OutboundQueryCompleted {
+                        result: QueryResult::GetRecord(Ok(GetRecordOk { records, .. })),
+                        ..
+                    })) => {
+                        for peer_record in records {
+                            self.on_advert(&peer_record.record.value);
+                        }
+                    },
+                    SwarmEvent::Behaviour(OracleEvent::Mdns(MdnsEvent::Discovered(list))) => {
+                        for (peer, address) in list {
+                            debug!("mdns discovered {:?} at {:?}", peer, address);
+                            self.swarm.behaviour_mut().kademlia.add_address(&peer, address.clone());
+                            if !self.swarm.is_connected(&peer) {
+                                if let Err(e) = self.swarm.dial(address.clone()) {
+                                    warn!("Dial {:?} failed: {:?}", address, e);
+                                }
                             }
-                        } else {
-                            warn!("message error:{:?}", cmd_result.err());
+                        }
+                    },
+                    SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                        // addresses dialed are reachable, those of listeners are ephemeral
+                        if endpoint.is_dialer() {
+                            self.swarm.behaviour_mut().kademlia.add_address(&peer_id, endpoint.get_remote_address().clone());
                         }
                     },
                     SwarmEvent::NewListenAddr { address, .. } => {
@@ -180,36 +606,189 @@ impl P2PMessageProcessor {
         }
     }
 
-    async fn sign_and_sendresponse(&mut self, valid_req: ValidateRequest, cfg: &Config) {
+    // collect_response puts a signed price into the bucket of its coin, once for each node
+    fn collect_response(
+        &mut self,
+        valid_resps: ValidateResponse,
+        signer: &String,
+        self_eth_address: &String,
+    ) {
+        info!("validate price response {:?}", valid_resps);
+        if !valid_resps.address.eq_ignore_ascii_case(signer) {
+            warn!("response of {} sent by {}", valid_resps.address, signer);
+            return;
+        }
+        let ts = utils::timestamp();
+        self.s_state
+            .lock()
+            .unwrap()
+            .peers
+            .insert(valid_resps.address.clone(), ts);
+        let bucket = match self.buckets.get(&valid_resps.coin) {
+            Some(bucket) => bucket,
+            None => {
+                warn!("response for unknown coin: {}", valid_resps.coin);
+                return;
+            }
+        };
+        let mut v_bucket = bucket.lock().unwrap();
+        let round_collection = v_bucket.entry(valid_resps.feed_count).or_default();
+        let check_dup = round_collection
+            .iter()
+            .find(|x| x.address == valid_resps.address);
+        if check_dup.is_none() && valid_resps.address != *self_eth_address {
+            round_collection.push(valid_resps);
+        }
+    }
+
+    // check_request checks a validation request against the local price, then has the contract check
+    // its requester is the leader off the loop; the reply is sent once the check is back, or refused now
+    fn check_request(&mut self, valid_req: ValidateRequest, requester: &String, reply: Reply) {
         debug!("validate price request {:?}", valid_req);
-        let price = valid_req.price.parse::<u128>().unwrap();
-        let price_local = *self.last_seen_price.lock().unwrap();
+        // the same request may come both directly and by gossip
+        let request_key = (valid_req.feed_count, valid_req.timestamp);
+        if self.last_signed.get(&valid_req.coin) == Some(&request_key) {
+            debug!("request already signed: {:?}", request_key);
+            return self.send_reply(reply, None);
+        }
+        let price = valid_req.price;
+        if price == 0 {
+            warn!("zero price requested of {}", valid_req.coin);
+            return self.send_reply(reply, None);
+        }
+        let price_local = self
+            .last_seen_price
+            .lock()
+            .unwrap()
+            .get(&valid_req.coin)
+            .copied();
+        let price_local = match price_local {
+            Some(price_local) => price_local,
+            None => {
+                warn!("no local price for coin: {}", valid_req.coin);
+                return self.send_reply(reply, None);
+            }
+        };
+        // sources of the requester disagree more than the tolerance, its price is not reliable
+        if valid_req.dispersion > MAX_PRICE_DIFF {
+            warn!(
+                "dispersion of requester too large: {} of {} sources",
+                valid_req.dispersion, valid_req.sources
+            );
+            return self.send_reply(reply, None);
+        }
         let diff = price_local.abs_diff(price) as f64 / price as f64;
-        if diff > 0.01 {
+        if diff > MAX_PRICE_DIFF {
             warn!("price diff too large: {} vs {}", price_local, price);
-            return;
+            return self.send_reply(reply, None);
         } else {
-            info!("price check: {} vs {}", price_local, price);
+            info!(
+                "price check: {} vs {}, dispersion {} of {} sources",
+                price_local, price, valid_req.dispersion, valid_req.sources
+            );
+        }
+        // only the leader of the current feed count is signed for
+        let oracle_stub = match self.oracles.get(&valid_req.coin) {
+            Some(oracle_stub) => oracle_stub.clone(),
+            None => {
+                warn!("no contract for coin: {}", valid_req.coin);
+                return self.send_reply(reply, None);
+            }
+        };
+        if self.pending_checks >= MAX_PENDING_CHECKS {
+            warn!(
+                "too many leader checks, request of {} refused",
+                valid_req.coin
+            );
+            return self.send_reply(reply, None);
+        }
+        self.pending_checks += 1;
+        let mut leaders = self
+            .leaders
+            .get(&valid_req.coin)
+            .cloned()
+            .unwrap_or_default();
+        let requester = requester.clone();
+        let check_send = self.check_send.clone();
+        tokio::task::spawn(async move {
+            let result =
+                eth::check_leader(&oracle_stub, &mut leaders, &requester, valid_req.feed_count)
+                    .await;
+            let check = LeaderCheck {
+                request: valid_req,
+                price_local: price_local,
+                leaders: leaders,
+                result: result,
+                reply: reply,
+            };
+            if check_send.unbounded_send(check).is_err() {
+                warn!("leader check dropped");
+            }
+        });
+    }
+
+    // on_leader_check keeps the leaders looked up, then signs the request if its requester leads
+    fn on_leader_check(&mut self, check: LeaderCheck, cfg: &Config) {
+        self.pending_checks -= 1;
+        let valid_req = check.request;
+        self.leaders.insert(valid_req.coin.clone(), check.leaders);
+        if let Err(err) = check.result {
+            warn!(
+                "refuse request of {} for feed count {}: {:?}",
+                valid_req.coin, valid_req.feed_count, err
+            );
+            return self.send_reply(check.reply, None);
+        }
+        // the copy of a request by the other way may have been signed meanwhile
+        let request_key = (valid_req.feed_count, valid_req.timestamp);
+        if self.last_signed.get(&valid_req.coin) == Some(&request_key) {
+            debug!("request already signed: {:?}", request_key);
+            return self.send_reply(check.reply, None);
         }
         let ts_seconds = utils::timestamp() / 1000;
         let (sig, signer_address) = eth::sign_price_info(
             cfg.private_key.clone(),
             valid_req.coin.clone(),
-            price_local,
+            check.price_local,
             ts_seconds,
         );
         debug!("sig:{}", sig);
+        self.last_signed.insert(valid_req.coin.clone(), request_key);
         let sig_response = CommandMessage::VResp(ValidateResponse {
             coin: valid_req.coin,
-            price: price_local.to_string(),
+            price: check.price_local,
             feed_count: valid_req.feed_count,
             sig: sig,
             timestamp: ts_seconds,
             address: signer_address,
         });
-        let sig_json = serde_json::to_string(&sig_response).unwrap();
-        if let Err(err) = self.publish_txt(sig_json) {
-            warn!("send response fail:{}", err);
+        let data = codec::encode_frame(&seal(&sig_response, &cfg.private_key));
+        self.send_reply(check.reply, Some((sig_response.coin().clone(), data)));
+    }
+
+    // send_reply publishes a signed response to the topic of its coin, or answers the requester directly;
+    // an empty response tells the leader the request is refused, while nothing is gossiped for it
+    fn send_reply(&mut self, reply: Reply, response: Option<(String, Vec<u8>)>) {
+        match reply {
+            Reply::Gossip => {
+                if let Some((coin, data)) = response {
+                    if let Err(err) = self.publish_frame(&coin, data) {
+                        warn!("send response fail:{}", err);
+                    }
+                }
+            }
+            Reply::Direct(peer, channel) => {
+                let data = response.map(|(_, data)| data).unwrap_or_default();
+                if self
+                    .swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_response(channel, data)
+                    .is_err()
+                {
+                    warn!("send response to {:?} fail", peer);
+                }
+            }
         }
     }
 }
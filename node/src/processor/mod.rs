@@ -1,3 +1,5 @@
+pub mod codec;
+pub mod discovery;
 pub mod gossip;
 pub mod swarm;
 pub mod utils;
@@ -1,26 +1,153 @@
 use crate::flags;
+use async_trait::async_trait;
+use futures::prelude::*;
 use libp2p;
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed, ProtocolName};
 use libp2p::gossipsub;
+use libp2p::gossipsub::GossipsubEvent;
 use libp2p::gossipsub::GossipsubMessage;
 use libp2p::gossipsub::MessageAuthenticity;
 use libp2p::gossipsub::MessageId;
 use libp2p::gossipsub::Topic;
 use libp2p::gossipsub::ValidationMode;
 use libp2p::identity;
+use libp2p::kad::record::store::MemoryStore;
+use libp2p::kad::{Kademlia, KademliaConfig, KademliaEvent};
+use libp2p::mdns::{Mdns, MdnsConfig, MdnsEvent};
+use libp2p::request_response::{
+    ProtocolSupport, RequestResponse, RequestResponseCodec, RequestResponseConfig,
+    RequestResponseEvent,
+};
+use libp2p::swarm::toggle::Toggle;
+use libp2p::NetworkBehaviour;
 use libp2p::PeerId;
 use std::collections::hash_map::DefaultHasher;
 use std::error::Error;
 use std::hash::{Hash, Hasher};
+use std::io;
+use std::iter;
 use std::time::Duration;
 
+// max size of a request or response of signature collection
+const MAX_SIG_MESSAGE_SIZE: usize = 64 * 1024;
+const SIG_REQUEST_TIMEOUT: u64 = 5000;
+const KAD_PROTOCOL: &[u8] = b"/ti-oracle/kad/1.0.0";
+
+// SigProtocol is the request-response protocol the leader uses to ask peers for signatures directly,
+// requests and responses are codec frames, the same bytes sent over gossip, an empty response is a refusal
+#[derive(Debug, Clone)]
+pub struct SigProtocol();
+
+impl ProtocolName for SigProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/ti-oracle/sig/1"
+    }
+}
+
+#[derive(Clone)]
+pub struct SigCodec();
+
+#[async_trait]
+impl RequestResponseCodec for SigCodec {
+    type Protocol = SigProtocol;
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+
+    async fn read_request<T>(&mut self, _: &SigProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_length_prefixed(io, MAX_SIG_MESSAGE_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &SigProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_length_prefixed(io, MAX_SIG_MESSAGE_SIZE).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &SigProtocol,
+        io: &mut T,
+        data: Vec<u8>,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, data).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &SigProtocol,
+        io: &mut T,
+        data: Vec<u8>,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, data).await?;
+        io.close().await
+    }
+}
+
+#[derive(NetworkBehaviour)]
+#[behaviour(out_event = "OracleEvent", event_process = false)]
+pub struct OracleBehaviour {
+    pub gossipsub: gossipsub::Gossipsub,
+    pub request_response: RequestResponse<SigCodec>,
+    pub kademlia: Kademlia<MemoryStore>, //holds peers and signed advertisements of nodes
+    pub mdns: Toggle<Mdns>,
+}
+
+#[derive(Debug)]
+pub enum OracleEvent {
+    Gossipsub(GossipsubEvent),
+    RequestResponse(RequestResponseEvent<Vec<u8>, Vec<u8>>),
+    Kademlia(KademliaEvent),
+    Mdns(MdnsEvent),
+}
+
+impl From<GossipsubEvent> for OracleEvent {
+    fn from(event: GossipsubEvent) -> Self {
+        OracleEvent::Gossipsub(event)
+    }
+}
+
+impl From<RequestResponseEvent<Vec<u8>, Vec<u8>>> for OracleEvent {
+    fn from(event: RequestResponseEvent<Vec<u8>, Vec<u8>>) -> Self {
+        OracleEvent::RequestResponse(event)
+    }
+}
+
+impl From<KademliaEvent> for OracleEvent {
+    fn from(event: KademliaEvent) -> Self {
+        OracleEvent::Kademlia(event)
+    }
+}
+
+impl From<MdnsEvent> for OracleEvent {
+    fn from(event: MdnsEvent) -> Self {
+        OracleEvent::Mdns(event)
+    }
+}
+
 pub async fn make_swarm(
     cfg: &flags::Config,
-) -> Result<(gossipsub::IdentTopic, libp2p::Swarm<gossipsub::Gossipsub>), Box<dyn Error>> {
+) -> Result<(Vec<gossipsub::IdentTopic>, libp2p::Swarm<OracleBehaviour>), Box<dyn Error>> {
     let local_key = identity::Keypair::generate_ed25519();
     let local_peer_id = PeerId::from(local_key.public());
     println!("Local peer id: {:?}", local_peer_id);
     let transport = libp2p::development_transport(local_key.clone()).await?;
-    let topic = Topic::new(cfg.coin_name.clone());
+    // one topic for each coin
+    let topics: Vec<gossipsub::IdentTopic> = cfg
+        .feeds
+        .iter()
+        .map(|feed| Topic::new(feed.coin_name.clone()))
+        .collect();
     let swarm = {
         // To content-address message, we can take the hash of message and use it as an ID.
         let message_id_fn = |message: &GossipsubMessage| {
@@ -41,10 +168,33 @@ pub async fn make_swarm(
         let mut gossipsub: gossipsub::Gossipsub =
             gossipsub::Gossipsub::new(MessageAuthenticity::Signed(local_key), gossipsub_config)
                 .expect("Correct configuration");
-        // subscribes to our topic
-        gossipsub.subscribe(&topic).unwrap();
+        // subscribes to our topics
+        for topic in &topics {
+            gossipsub.subscribe(topic).unwrap();
+        }
+        let mut rr_config = RequestResponseConfig::default();
+        rr_config.set_request_timeout(Duration::from_millis(SIG_REQUEST_TIMEOUT));
+        let request_response = RequestResponse::new(
+            SigCodec(),
+            iter::once((SigProtocol(), ProtocolSupport::Full)),
+            rr_config,
+        );
+        let mut kad_config = KademliaConfig::default();
+        kad_config.set_protocol_name(KAD_PROTOCOL);
+        let kademlia =
+            Kademlia::with_config(local_peer_id, MemoryStore::new(local_peer_id), kad_config);
+        let mdns = match cfg.discovery.mdns {
+            true => Some(Mdns::new(MdnsConfig::default()).await?),
+            false => None,
+        };
         // build the swarm
-        libp2p::Swarm::new(transport, gossipsub, local_peer_id)
+        let behaviour = OracleBehaviour {
+            gossipsub: gossipsub,
+            request_response: request_response,
+            kademlia: kademlia,
+            mdns: Toggle::from(mdns),
+        };
+        libp2p::Swarm::new(transport, behaviour, local_peer_id)
     };
-    Ok((topic, swarm))
+    Ok((topics, swarm))
 }
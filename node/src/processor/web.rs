@@ -1,3 +1,5 @@
+use crate::fetcher::aggregator::PriceResult;
+use crate::fetcher::resilience::ExchangeHealth;
 use crate::fetcher::PairInfo;
 use crate::processor::gossip::ValidateResponse;
 use axum::{
@@ -30,12 +32,38 @@ pub struct PeerReport {
     pub address: String,
 }
 
+// PairsSnapshot keeps pairs used by the latest price calculation, and the ones dropped
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PairsSnapshot {
+    pub accepted: Vec<PairInfo>,
+    pub rejected: Vec<RejectedPair>,
+    pub skipped: Vec<SkippedSymbol>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RejectedPair {
+    #[serde(flatten)]
+    pub pair: PairInfo,
+    pub reason: String,
+}
+
+// SkippedSymbol is a configured symbol which can not be evaluated, e.g. one leg is missing
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SkippedSymbol {
+    pub exchange: String,
+    pub symbol: String,
+    pub reason: String,
+}
+
 #[derive(Default)]
 pub struct SharedStateData {
-    pub peers_report: BTreeMap<u64, Vec<ValidateResponse>>,
-    pub exchange_pairs: Vec<PairInfo>,
-    pub peers: BTreeMap<String, u64>, //peer, timestamp
+    pub peers_report: BTreeMap<String, BTreeMap<u64, Vec<ValidateResponse>>>, //coin -> feed count -> reports
+    pub exchange_pairs: BTreeMap<String, PairsSnapshot>,                      //coin -> pairs
+    pub prices: BTreeMap<String, PriceResult>,                                //coin -> latest price
+    pub exchange_health: BTreeMap<String, ExchangeHealth>,                    //exchange -> health
+    pub peers: BTreeMap<String, u64>,                                         //peer, timestamp
     pub chain_events: Vec<ChainEvent>,
+    pub nodes: BTreeMap<String, BTreeMap<String, bool>>, //coin -> node address -> whitelisted, cached from the contract
 }
 
 pub type SharedState = Arc<Mutex<SharedStateData>>;
@@ -57,6 +85,8 @@ pub async fn start(web_addr: String, s_state: SharedState) {
         )
         .route("/report", get(report))
         .route("/pairs", get(pairs))
+        .route("/prices", get(prices))
+        .route("/health", get(health))
         .route("/peers", get(peers))
         .route("/events", get(events))
         .layer(ServiceBuilder::new().layer(Extension(s_state)).into_inner());
@@ -80,6 +110,16 @@ async fn pairs(Extension(state): Extension<SharedState>) -> impl IntoResponse {
     (StatusCode::ACCEPTED, Json(exchange_pairs))
 }
 
+async fn prices(Extension(state): Extension<SharedState>) -> impl IntoResponse {
+    let prices = state.lock().unwrap().prices.clone();
+    (StatusCode::ACCEPTED, Json(prices))
+}
+
+async fn health(Extension(state): Extension<SharedState>) -> impl IntoResponse {
+    let health = state.lock().unwrap().exchange_health.clone();
+    (StatusCode::ACCEPTED, Json(health))
+}
+
 async fn peers(Extension(state): Extension<SharedState>) -> impl IntoResponse {
     let peers = state.lock().unwrap().peers.clone();
     (StatusCode::ACCEPTED, Json(peers))